@@ -0,0 +1,149 @@
+// Agent-level finite state machines. Each state can override named species parameters
+// (as accepted by PreyParams::set / PredParams::set) and transitions fire on perception
+// and time spent in the current state.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Behaviour {
+    // Agents without a state machine
+    Default,
+    // Prey
+    Grazing,
+    Vigilant,
+    Fleeing,
+    // Predators
+    Searching,
+    Stalking,
+    Attacking,
+    Handling,
+}
+
+impl Behaviour {
+    pub fn colour(&self) -> [f32; 4] {
+        match self {
+            Behaviour::Default => [1.0, 1.0, 1.0, 1.0],
+            Behaviour::Grazing => [0.2, 0.7, 0.2, 1.0],
+            Behaviour::Vigilant => [0.9, 0.8, 0.1, 1.0],
+            Behaviour::Fleeing => [0.9, 0.4, 0.0, 1.0],
+            Behaviour::Searching => [0.3, 0.3, 0.3, 1.0],
+            Behaviour::Stalking => [0.5, 0.0, 0.5, 1.0],
+            Behaviour::Attacking => [0.8, 0.0, 0.0, 1.0],
+            Behaviour::Handling => [0.4, 0.2, 0.0, 1.0],
+        }
+    }
+}
+
+// What an agent sensed during the current step
+#[derive(Debug, Clone)]
+pub struct Perception {
+    // Distances to the nearest agent of each species, infinite if none was seen
+    pub nearest_predator: f32,
+    pub nearest_prey: f32,
+    pub alarm: f32,
+    pub kill_cooldown: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Condition {
+    PredatorWithin(f32),
+    PredatorBeyond(f32),
+    PreyWithin(f32),
+    PreyBeyond(f32),
+    TimeInState(f32),
+    AlarmAbove(f32),
+    // Predator is still in its post-kill cooldown
+    RecentKill,
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn holds(&self, time_in_state: f32, perception: &Perception) -> bool {
+        match self {
+            Condition::PredatorWithin(r) => perception.nearest_predator < *r,
+            Condition::PredatorBeyond(r) => perception.nearest_predator >= *r,
+            Condition::PreyWithin(r) => perception.nearest_prey < *r,
+            Condition::PreyBeyond(r) => perception.nearest_prey >= *r,
+            Condition::TimeInState(t) => time_in_state >= *t,
+            Condition::AlarmAbove(level) => perception.alarm > *level,
+            Condition::RecentKill => perception.kill_cooldown > 0.0,
+            Condition::All(conditions) => conditions
+                .iter()
+                .all(|c| c.holds(time_in_state, perception)),
+            Condition::Any(conditions) => conditions
+                .iter()
+                .any(|c| c.holds(time_in_state, perception)),
+        }
+    }
+
+    // Perception range needed to evaluate the condition
+    fn radius(&self) -> f32 {
+        match self {
+            Condition::PredatorWithin(r)
+            | Condition::PredatorBeyond(r)
+            | Condition::PreyWithin(r)
+            | Condition::PreyBeyond(r) => *r,
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                conditions.iter().map(|c| c.radius()).fold(0.0, f32::max)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transition {
+    pub from: Behaviour,
+    pub to: Behaviour,
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateMachine {
+    pub initial: Behaviour,
+    pub overrides: Vec<(Behaviour, Vec<(String, f32)>)>,
+    // Checked in order, the first matching transition out of the current state fires
+    pub transitions: Vec<Transition>,
+}
+
+impl StateMachine {
+    pub fn overrides(&self, state: Behaviour) -> &[(String, f32)] {
+        self.overrides
+            .iter()
+            .find(|(s, _)| *s == state)
+            .map_or(&[], |(_, o)| o.as_slice())
+    }
+
+    pub fn next(
+        &self,
+        state: Behaviour,
+        time_in_state: f32,
+        perception: &Perception,
+    ) -> Option<Behaviour> {
+        self.transitions
+            .iter()
+            .find(|t| t.from == state && t.condition.holds(time_in_state, perception))
+            .map(|t| t.to)
+    }
+
+    // Apply every override through set, reporting the first name it rejects
+    pub fn check_overrides<F: FnMut(&str, f32) -> Result<(), String>>(
+        &self,
+        mut set: F,
+    ) -> Result<(), String> {
+        for (state, overrides) in self.overrides.iter() {
+            for (name, value) in overrides.iter() {
+                set(name, *value).map_err(|e| format!("{:?}: {}", state, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn max_radius(&self) -> f32 {
+        self.transitions
+            .iter()
+            .map(|t| t.condition.radius())
+            .fold(0.0, f32::max)
+    }
+}
@@ -1,11 +1,16 @@
+use crate::behaviour::{Behaviour, Perception, StateMachine};
 use crate::graphics::{
-    GUIPredParams, GUIPreyParams, PlayState, BOID_SIZE, CREAM, LRED, WINDOW_HEIGHT, WINDOW_WIDTH,
+    GUIPredParams, GUIPreyParams, PlayState, ALARMED, BOID_SIZE, CREAM, INFORMED, WINDOW_WIDTH,
 };
-use crate::model::Time;
+use crate::hunting::{Cooperation, HuntState, Search};
+use crate::kernel::{Kernel, Normalisation};
+use crate::model::{distance_vec, Time, BC, IC};
 use ggez::glam::{Mat2, Vec2};
 use ggez::{graphics, Context};
+use std::borrow::Cow;
+use std::iter::repeat_n;
 use rand::Rng;
-use rand_distr::{Distribution, Normal, NormalError};
+use serde::{Deserialize, Serialize};
 
 pub enum Clamped {
     Min(f32),
@@ -23,15 +28,105 @@ pub fn clamp(val: f32, min: f32, max: f32) -> Clamped {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AgentType {
     Prey([f32; 4], PreyParams),
     Predator([f32; 4], PredParams),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Kinematics {
+    // Acceleration capped at max_acceleration and speed at max_vel
+    Free,
+    // Heading turns by at most max_turn_rate (rad/s), speed changes by at most
+    // max_speed_change per second and stays between min_speed and max_vel
+    TurnRate {
+        max_turn_rate: f32,
+        max_speed_change: f32,
+        min_speed: f32,
+    },
+}
+
+impl Kinematics {
+    // Setting one limit on Free kinematics switches to TurnRate with the others left open
+    pub fn set(&mut self, name: &str, value: f32) -> Result<(), String> {
+        if let Kinematics::Free = self {
+            *self = Kinematics::TurnRate {
+                max_turn_rate: f32::MAX,
+                max_speed_change: f32::MAX,
+                min_speed: 0.0,
+            };
+        }
+        if let Kinematics::TurnRate { max_turn_rate, max_speed_change, min_speed } = self {
+            match name {
+                "max_turn_rate" => *max_turn_rate = value,
+                "max_speed_change" => *max_speed_change = value,
+                "min_speed" => *min_speed = value,
+                _ => return Err(format!("Unknown kinematics parameter: {}", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Startle state spread between prey. Levels run from 0.0 (calm) to 1.0 (seeing a predator).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmParams {
+    // Distance over which alarm spreads between prey
+    pub radius: f32,
+    // Exponential decay rate per second
+    pub decay: f32,
+    // Fraction of a neighbour's alarm level passed on
+    pub transmission: f32,
+    // Neighbours below this level do not pass on their alarm
+    pub threshold: f32,
+    // Fractional increase in max_vel and predator_repulsion at full alarm
+    pub speed_gain: f32,
+    pub repulsion_gain: f32,
+}
+
+// Preference carried by informed prey. Positions and radii are fractions of bound_length.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Goal {
+    Direction(Vec2),
+    Target { position: Vec2, radius: f32 },
+}
+
+impl Goal {
+    // Unit vector an informed agent at pos would like to move along
+    pub fn direction(&self, pos: &Vec2, bound_length: f32, bc: &BC) -> Vec2 {
+        match self {
+            Goal::Direction(dir) => dir.normalize_or_zero(),
+            Goal::Target { position, .. } => {
+                distance_vec(pos, &(*position * bound_length), bound_length, bc).normalize_or_zero()
+            }
+        }
+    }
+
+    pub fn reached(&self, pos: &Vec2, bound_length: f32, bc: &BC) -> bool {
+        match self {
+            Goal::Direction(_) => false,
+            Goal::Target { position, radius } => {
+                distance_vec(pos, &(*position * bound_length), bound_length, bc).length()
+                    < radius * bound_length
+            }
+        }
+    }
+}
+
+// Fields left out of a scenario file take their values from PredParams::new
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct PredParams {
-    pub vision_radius: f32,
+    pub chase_radius: f32,
+    pub align_radius: f32,
+    pub attract_radius: f32,
+    pub repel_radius: f32,
+    pub chase_kernel: Kernel,
+    pub align_kernel: Kernel,
+    pub repel_kernel: Kernel,
+    pub normalisation: Normalisation,
     pub current_direction: f32,
     pub prey_alignment: f32,
     pub prey_attraction: f32,
@@ -39,15 +134,46 @@ pub struct PredParams {
     pub predator_alignment: f32,
     pub predator_attraction: f32,
     pub predator_repulsion: f32,
+    // Seconds spent pursuing the last-seen prey position once prey are out of sight
+    pub memory_duration: f32,
+    // Search pattern used with no prey in sight or memory
+    pub search: Search,
+    pub search_weight: f32,
+    pub cooperation: Option<Cooperation>,
+    pub pheromone_deposit: f32,
+    pub pheromone_response: f32,
     pub max_acceleration: f32,
     pub max_vel: f32,
+    pub noise: f32,
+    // Standard deviation of the error on perceived neighbour positions and velocities
+    pub position_noise: f32,
+    pub velocity_noise: f32,
+    // Neighbours are perceived as they were this many steps ago
+    pub reaction_delay: usize,
+    pub kinematics: Kinematics,
+    pub body_radius: f32,
+    // Optional behavioural states with per-state parameter overrides
+    pub behaviour: Option<StateMachine>,
+    // Strength of wall avoidance under BC::Soft, relative to max_acceleration
     pub boundary: f32,
+    // Distance at which walls are felt, overrides the BC::Soft range when set
+    pub boundary_range: Option<f32>,
     pub cooldown: f32,
 }
 
-#[derive(Debug, Clone)]
+// Fields left out of a scenario file take their values from PreyParams::new
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct PreyParams {
-    pub vision_radius: f32,
+    pub align_radius: f32,
+    pub attract_radius: f32,
+    pub repel_radius: f32,
+    pub threat_radius: f32,
+    pub align_kernel: Kernel,
+    pub attract_kernel: Kernel,
+    pub repel_kernel: Kernel,
+    pub threat_kernel: Kernel,
+    pub normalisation: Normalisation,
     pub current_direction: f32,
     pub prey_alignment: f32,
     pub prey_attraction: f32,
@@ -55,9 +181,44 @@ pub struct PreyParams {
     pub predator_alignment: f32,
     pub predator_centering: f32,
     pub predator_repulsion: f32,
+    // Goal followed by informed prey, weighted against the social forces by goal_weight
+    pub goal: Option<Goal>,
+    pub goal_weight: f32,
+    // Fraction of prey that are informed
+    pub informed_fraction: f32,
+    pub alarm: Option<AlarmParams>,
+    // Pull towards the nearest refuge, scaled by (1 + refuge_fear_gain) when a predator is seen
+    pub refuge_attraction: f32,
+    pub refuge_fear_gain: f32,
+    // Pull up the resource gradient and the most that can be eaten per second
+    pub forage_weight: f32,
+    pub intake_rate: f32,
+    // Amount laid into the pheromone field per second and the pull up its gradient
+    // (negative to avoid it)
+    pub pheromone_deposit: f32,
+    pub pheromone_response: f32,
     pub max_acceleration: f32,
     pub max_vel: f32,
+    pub noise: f32,
+    // Standard deviation of the error on perceived neighbour positions and velocities
+    pub position_noise: f32,
+    pub velocity_noise: f32,
+    // Neighbours are perceived as they were this many steps ago
+    pub reaction_delay: usize,
+    pub kinematics: Kinematics,
+    pub body_radius: f32,
+    // Optional behavioural states with per-state parameter overrides
+    pub behaviour: Option<StateMachine>,
+    // Strength of wall avoidance under BC::Soft, relative to max_acceleration
     pub boundary: f32,
+    // Distance at which walls are felt, overrides the BC::Soft range when set
+    pub boundary_range: Option<f32>,
+}
+
+impl Default for PreyParams {
+    fn default() -> PreyParams {
+        PreyParams::new()
+    }
 }
 
 impl PreyParams {
@@ -70,10 +231,36 @@ impl PreyParams {
             predator_alignment: (0.0),
             predator_centering: (0.0),
             predator_repulsion: (0.0),
-            boundary: (20.0),
+            goal: None,
+            goal_weight: 0.0,
+            informed_fraction: 0.0,
+            alarm: None,
+            refuge_attraction: 0.0,
+            refuge_fear_gain: 0.0,
+            forage_weight: 0.0,
+            intake_rate: 0.0,
+            pheromone_deposit: 0.0,
+            pheromone_response: 0.0,
+            boundary: 1.0,
+            boundary_range: None,
             max_acceleration: 2.0,
             max_vel: 1.0,
-            vision_radius: 1.0,
+            noise: 0.05,
+            position_noise: 0.0,
+            velocity_noise: 0.0,
+            reaction_delay: 0,
+            kinematics: Kinematics::Free,
+            body_radius: 0.0,
+            behaviour: None,
+            align_radius: 1.0,
+            attract_radius: 1.0,
+            repel_radius: 1.0,
+            threat_radius: 1.0,
+            align_kernel: Kernel::Uniform,
+            attract_kernel: Kernel::InversePower(-1.0),
+            repel_kernel: Kernel::InversePower(1.0),
+            threat_kernel: Kernel::InversePower(1.0),
+            normalisation: Normalisation::Mean,
         }
     }
 
@@ -173,12 +360,15 @@ impl PreyParams {
             Ok(v) => boundary = v,
             Err(_E) => {
                 println!("Please enter a valid boundary. Setting to default");
-                boundary = 20.0;
-                gui_params.boundary = 20.0.to_string();
+                boundary = 1.0;
+                gui_params.boundary = 1.0.to_string();
             }
         };
         PreyParams {
-            vision_radius,
+            align_radius: vision_radius,
+            attract_radius: vision_radius,
+            repel_radius: vision_radius,
+            threat_radius: vision_radius,
             current_direction,
             prey_attraction,
             prey_alignment,
@@ -189,7 +379,78 @@ impl PreyParams {
             max_acceleration,
             max_vel,
             boundary,
+            ..PreyParams::new()
+        }
+    }
+
+    // Largest distance at which anything is perceived, sets the grid search width
+    pub fn max_radius(&self) -> f32 {
+        let alarm_radius = self.alarm.as_ref().map_or(0.0, |alarm| alarm.radius);
+        let behaviour_radius = self.behaviour.as_ref().map_or(0.0, |fsm| fsm.max_radius());
+        self.align_radius
+            .max(self.attract_radius)
+            .max(self.repel_radius)
+            .max(self.threat_radius)
+            .max(alarm_radius)
+            .max(behaviour_radius)
+    }
+
+    // Set a single parameter by name, used by scheduled events
+    pub fn set(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "align_radius" => self.align_radius = value,
+            "attract_radius" => self.attract_radius = value,
+            "repel_radius" => self.repel_radius = value,
+            "threat_radius" => self.threat_radius = value,
+            "current_direction" => self.current_direction = value,
+            "prey_alignment" => self.prey_alignment = value,
+            "prey_attraction" => self.prey_attraction = value,
+            "prey_repulsion" => self.prey_repulsion = value,
+            "predator_alignment" => self.predator_alignment = value,
+            "predator_centering" => self.predator_centering = value,
+            "predator_repulsion" => self.predator_repulsion = value,
+            "max_acceleration" => self.max_acceleration = value,
+            "max_vel" => self.max_vel = value,
+            "goal_weight" => self.goal_weight = value,
+            "informed_fraction" => self.informed_fraction = value,
+            "alarm_radius" | "alarm_decay" | "alarm_transmission" | "alarm_threshold"
+            | "alarm_speed_gain" | "alarm_repulsion_gain" => {
+                let alarm = self
+                    .alarm
+                    .as_mut()
+                    .ok_or(format!("Alarm is disabled, cannot set {}", name))?;
+                match name {
+                    "alarm_radius" => alarm.radius = value,
+                    "alarm_decay" => alarm.decay = value,
+                    "alarm_transmission" => alarm.transmission = value,
+                    "alarm_threshold" => alarm.threshold = value,
+                    "alarm_speed_gain" => alarm.speed_gain = value,
+                    _ => alarm.repulsion_gain = value,
+                }
+            }
+            "refuge_attraction" => self.refuge_attraction = value,
+            "refuge_fear_gain" => self.refuge_fear_gain = value,
+            "forage_weight" => self.forage_weight = value,
+            "intake_rate" => self.intake_rate = value,
+            "pheromone_deposit" => self.pheromone_deposit = value,
+            "pheromone_response" => self.pheromone_response = value,
+            "position_noise" => self.position_noise = value,
+            "velocity_noise" => self.velocity_noise = value,
+            "reaction_delay" => self.reaction_delay = value.max(0.0).round() as usize,
+            "max_turn_rate" | "max_speed_change" | "min_speed" => self.kinematics.set(name, value)?,
+            "noise" => self.noise = value,
+            "body_radius" => self.body_radius = value,
+            "boundary" => self.boundary = value,
+            "boundary_range" => self.boundary_range = Some(value),
+            _ => return Err(format!("Unknown prey parameter: {}", name)),
         }
+        Ok(())
+    }
+}
+
+impl Default for PredParams {
+    fn default() -> PredParams {
+        PredParams::new()
     }
 }
 
@@ -203,10 +464,31 @@ impl PredParams {
             predator_alignment: (0.0),
             predator_attraction: (0.0),
             predator_repulsion: (0.0),
-            boundary: (20.0),
+            memory_duration: 0.0,
+            search: Search::Wander,
+            search_weight: 0.0,
+            cooperation: None,
+            pheromone_deposit: 0.0,
+            pheromone_response: 0.0,
+            boundary: 1.0,
+            boundary_range: None,
             max_acceleration: 3.0,
             max_vel: 1.0,
-            vision_radius: 2.0,
+            noise: 0.05,
+            position_noise: 0.0,
+            velocity_noise: 0.0,
+            reaction_delay: 0,
+            kinematics: Kinematics::Free,
+            body_radius: 0.0,
+            behaviour: None,
+            chase_radius: 2.0,
+            align_radius: 2.0,
+            attract_radius: 2.0,
+            repel_radius: 2.0,
+            chase_kernel: Kernel::InversePower(2.0),
+            align_kernel: Kernel::Uniform,
+            repel_kernel: Kernel::InversePower(1.0),
+            normalisation: Normalisation::Mean,
             cooldown: 0.5,
         }
     }
@@ -307,12 +589,15 @@ impl PredParams {
             Ok(v) => boundary = v,
             Err(_E) => {
                 println!("Please enter a valid boundary. Setting to default");
-                boundary = 10.0;
-                gui_params.boundary = 10.0.to_string();
+                boundary = 1.0;
+                gui_params.boundary = 1.0.to_string();
             }
         };
         PredParams {
-            vision_radius,
+            chase_radius: vision_radius,
+            align_radius: vision_radius,
+            attract_radius: vision_radius,
+            repel_radius: vision_radius,
             current_direction,
             prey_alignment,
             prey_attraction,
@@ -324,7 +609,63 @@ impl PredParams {
             max_vel,
             boundary,
             cooldown: 0.0,
+            ..PredParams::new()
+        }
+    }
+
+    pub fn max_radius(&self) -> f32 {
+        let behaviour_radius = self.behaviour.as_ref().map_or(0.0, |fsm| fsm.max_radius());
+        let comm_radius = self.cooperation.as_ref().map_or(0.0, |coop| coop.comm_radius);
+        self.chase_radius
+            .max(self.align_radius)
+            .max(self.attract_radius)
+            .max(self.repel_radius)
+            .max(behaviour_radius)
+            .max(comm_radius)
+    }
+
+    // Set a single parameter by name, used by scheduled events
+    pub fn set(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "chase_radius" => self.chase_radius = value,
+            "align_radius" => self.align_radius = value,
+            "attract_radius" => self.attract_radius = value,
+            "repel_radius" => self.repel_radius = value,
+            "current_direction" => self.current_direction = value,
+            "prey_alignment" => self.prey_alignment = value,
+            "prey_attraction" => self.prey_attraction = value,
+            "nearest_prey" => self.nearest_prey = value,
+            "predator_alignment" => self.predator_alignment = value,
+            "predator_attraction" => self.predator_attraction = value,
+            "predator_repulsion" => self.predator_repulsion = value,
+            "max_acceleration" => self.max_acceleration = value,
+            "max_vel" => self.max_vel = value,
+            "memory_duration" => self.memory_duration = value,
+            "search_weight" => self.search_weight = value,
+            "comm_radius" | "flank_weight" => {
+                let coop = self
+                    .cooperation
+                    .as_mut()
+                    .ok_or(format!("Cooperation is disabled, cannot set {}", name))?;
+                match name {
+                    "comm_radius" => coop.comm_radius = value,
+                    _ => coop.flank_weight = value,
+                }
+            }
+            "pheromone_deposit" => self.pheromone_deposit = value,
+            "pheromone_response" => self.pheromone_response = value,
+            "position_noise" => self.position_noise = value,
+            "velocity_noise" => self.velocity_noise = value,
+            "reaction_delay" => self.reaction_delay = value.max(0.0).round() as usize,
+            "max_turn_rate" | "max_speed_change" | "min_speed" => self.kinematics.set(name, value)?,
+            "noise" => self.noise = value,
+            "body_radius" => self.body_radius = value,
+            "boundary" => self.boundary = value,
+            "boundary_range" => self.boundary_range = Some(value),
+            "cooldown" => self.cooldown = value,
+            _ => return Err(format!("Unknown predator parameter: {}", name)),
         }
+        Ok(())
     }
 }
 
@@ -356,18 +697,36 @@ impl AgentType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Agent {
+    // Stable identifier assigned by the Model, independent of the index in Model::agents
+    pub id: usize,
     pub positions: Vec<Vec2>,
     pub velocities: Vec<Vec2>,
-    points: Vec<Vec2>,
+    // Rebuilt by ensure_polygon after loading a checkpoint
+    #[serde(skip)]
     polygon: Option<graphics::Mesh>,
     pub agent_type: AgentType,
     pub kill_cooldown: f32,
     pub dead: State,
+    pub birth_index: usize,
+    // Informed prey follow their species' goal
+    pub informed: bool,
+    // Alarm level at each time step, always 0.0 for predators
+    pub alarm: Vec<f32>,
+    // Individually drawn parameter values, see Parameters::prey_variation
+    pub traits: Vec<(String, f32)>,
+    // Behavioural state at each time step
+    pub behaviour: Vec<Behaviour>,
+    pub time_in_behaviour: f32,
+    pub hunt: HuntState,
+    // Ids of prey killed by this agent
+    pub kills: Vec<usize>,
+    // Resource eaten at each time step
+    pub intake: Vec<f32>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum State{
     Alive,
     Dead(usize,Vec2),
@@ -375,127 +734,162 @@ pub enum State{
 
 impl Agent {
     pub fn new(b_length: f32, agent_type: AgentType) -> Agent {
-        let mut a_vec = match agent_type {
-            AgentType::Prey(..) => {
-                let y: f32 = rand::thread_rng().gen_range(2.0/10.0..1.0) * b_length;
-                let x: f32 = rand::thread_rng().gen_range(0.0..1.0) * b_length;
-                Vec2::new(x, y)
-            },
-            AgentType::Predator(..) => {
-                let y: f32 = rand::thread_rng().gen_range(0.0..1.0/10.0) * b_length;
-                let x: f32 = rand::thread_rng().gen_range(0.0..1.0) * b_length;
-                Vec2::new(x, y)
-            },
+        let ic = match agent_type {
+            AgentType::Prey(..) => IC::prey_default(),
+            AgentType::Predator(..) => IC::pred_default(),
         };
-        // Change to include negatives
-        let mut rng = rand::thread_rng();
-        let normal = Normal::new(0.0, 0.1).unwrap();
-        let x = normal.sample(&mut rng);
-        let y = normal.sample(&mut rng);
-        let mut v_vec = Vec2::new(x, y);
-        v_vec = v_vec.normalize();
-        let mut kill_cooldown = 0.0;
-
-        let multiplier;
-        match &agent_type {
-            AgentType::Prey(..) => multiplier = 1.0,
-            AgentType::Predator(_, params) => { multiplier = 1.5; kill_cooldown = params.cooldown },
-        }
-
-        let point_1 = Vec2::new(0.0, -(BOID_SIZE*multiplier) / 2.0);
-        let point_2 = Vec2::new((BOID_SIZE*multiplier) / 4.0, (BOID_SIZE*multiplier) / 2.0);
-        let point_3 = Vec2::new(0.0, (BOID_SIZE*multiplier) / 3.0);
-        let point_4 = Vec2::new(-(BOID_SIZE*multiplier) / 4.0, (BOID_SIZE*multiplier) / 2.0);
+        let (position, velocity) = ic.generate(1, b_length, &mut rand::thread_rng())[0];
+        Agent::with_state(agent_type, position, velocity)
+    }
 
-        let last_pos = a_vec.clone();
+    pub fn with_state(agent_type: AgentType, position: Vec2, velocity: Vec2) -> Agent {
+        let mut kill_cooldown = 0.0;
+        let fsm = match &agent_type {
+            AgentType::Prey(_, params) => params.behaviour.as_ref(),
+            AgentType::Predator(_, params) => {
+                kill_cooldown = params.cooldown;
+                params.behaviour.as_ref()
+            }
+        };
+        let behaviour = fsm.map_or(Behaviour::Default, |fsm| fsm.initial);
 
         Agent {
-            positions: {
-                let mut pos_vec: Vec<Vec2> = Vec::new();
-                pos_vec.push(a_vec);
-                pos_vec
-            },
-            velocities: {
-                let mut vel_vec: Vec<Vec2> = Vec::new();
-                vel_vec.push(v_vec);
-                vel_vec
-            },
-            points: vec![point_1, point_2, point_3, point_4],
+            id: 0,
+            positions: vec![position],
+            velocities: vec![velocity],
             polygon: None,
             agent_type,
             dead: State::Alive,
             kill_cooldown,
+            birth_index: 0,
+            informed: false,
+            alarm: vec![0.0],
+            traits: Vec::new(),
+            behaviour: vec![behaviour],
+            time_in_behaviour: 0.0,
+            hunt: HuntState::new(),
+            kills: Vec::new(),
+            intake: vec![0.0],
         }
     }
+
     pub fn new_graphical(ctx: &mut Context, b_length: f32, agent_type: AgentType) -> Agent {
-        let mut a_vec = match agent_type {
-            AgentType::Prey(..) => {
-                let y: f32 = rand::thread_rng().gen_range(2.0/10.0..1.0) * b_length;
-                let x: f32 = rand::thread_rng().gen_range(0.0..1.0) * b_length;
-                Vec2::new(x, y)
-            },
-            AgentType::Predator(..) => {
-                let y: f32 = rand::thread_rng().gen_range(0.0..1.0/10.0) * b_length;
-                let x: f32 = rand::thread_rng().gen_range(0.0..1.0) * b_length;
-                // let y: f32 = 0.5*b_length;
-                // let x: f32 = 0.5*b_length;
-                Vec2::new(x, y)
-            },
-        };
+        let mut agent = Agent::new(b_length, agent_type);
+        agent.ensure_polygon(ctx, WINDOW_WIDTH / b_length);
+        agent
+    }
 
-        // Change to include negatives
-        let mut rng = rand::thread_rng();
-        let normal = Normal::new(0.0, 0.1).unwrap();
-        let x = normal.sample(&mut rng);
-        let y = normal.sample(&mut rng);
-        let mut v_vec = Vec2::new(x, y);
-        v_vec = v_vec.normalize();
-        let mut kill_cooldown = 0.0;
+    pub fn body_radius(&self) -> f32 {
+        match &self.agent_type {
+            AgentType::Prey(_, params) => params.body_radius,
+            AgentType::Predator(_, params) => params.body_radius,
+        }
+    }
+
+    // Agents added mid-run hold their spawn state for every earlier time step so
+    // that positions[t] and velocities[t] stay valid for the whole run
+    pub fn pad_history(&mut self, current_index: usize) {
+        let k = (current_index + 1).saturating_sub(self.positions.len());
+        let first_pos = self.positions[0];
+        let first_vel = self.velocities[0];
+        let first_behaviour = self.behaviour[0];
+        self.positions.splice(0..0, repeat_n(first_pos, k));
+        self.velocities.splice(0..0, repeat_n(first_vel, k));
+        self.alarm.splice(0..0, repeat_n(0.0, k));
+        self.behaviour.splice(0..0, repeat_n(first_behaviour, k));
+        self.intake.splice(0..0, repeat_n(0.0, k));
+        self.birth_index = current_index;
+    }
 
-        let multiplier;
-        match &agent_type {
-            AgentType::Prey(..) => multiplier = 1.0,
-            AgentType::Predator(_, params) => { multiplier = 1.5; kill_cooldown = params.cooldown },
+    // Forget the first n time steps, always keeping the latest
+    pub fn drop_history(&mut self, n: usize) {
+        let keep_last = |len: usize| n.min(len.saturating_sub(1));
+        self.positions.drain(..keep_last(self.positions.len()));
+        self.velocities.drain(..keep_last(self.velocities.len()));
+        self.alarm.drain(..keep_last(self.alarm.len()));
+        self.behaviour.drain(..keep_last(self.behaviour.len()));
+        self.intake.drain(..keep_last(self.intake.len()));
+        self.birth_index = self.birth_index.saturating_sub(n);
+        if let State::Dead(index, _) = &mut self.dead {
+            *index = index.saturating_sub(n);
         }
-        let point_1 = Vec2::new(0.0, -(BOID_SIZE)*multiplier / 2.0);
-        let point_2 = Vec2::new((BOID_SIZE)*multiplier / 4.0, (BOID_SIZE)*multiplier / 2.0);
-        let point_3 = Vec2::new(0.0, (BOID_SIZE)*multiplier / 3.0);
-        let point_4 = Vec2::new(-(BOID_SIZE)*multiplier / 4.0, (BOID_SIZE)*multiplier / 2.0);
-
-        let last_pos = a_vec.clone();
-        let polygon_matrix = [
-            (point_1.clone()) + last_pos,
-            (point_2.clone()) + last_pos,
-            (point_3.clone()) + last_pos,
-            (point_4.clone()) + last_pos,
+    }
+
+    // Agents spawned without a graphics context get their mesh on first draw
+    pub fn ensure_polygon(&mut self, ctx: &mut Context, scale: f32) {
+        if self.polygon.is_some() {
+            return;
+        }
+        // Agents with a body are drawn at their physical size, point agents at a fixed size
+        let size = if self.body_radius() > 0.0 {
+            2.0 * self.body_radius() * scale
+        } else {
+            match &self.agent_type {
+                AgentType::Prey(..) => BOID_SIZE,
+                AgentType::Predator(..) => BOID_SIZE * 1.5,
+            }
+        };
+        let points = [
+            Vec2::new(0.0, -size / 2.0),
+            Vec2::new(size / 4.0, size / 2.0),
+            Vec2::new(0.0, size / 3.0),
+            Vec2::new(-size / 4.0, size / 2.0),
         ];
+        let last_pos = *self.positions.last().unwrap();
+        let polygon_matrix: Vec<Vec2> = points.iter().map(|p| *p + last_pos).collect();
+        self.polygon = Some(
+            graphics::Mesh::new_polygon(
+                ctx,
+                graphics::DrawMode::fill(),
+                &polygon_matrix,
+                graphics::Color::from(CREAM),
+            )
+            .unwrap(),
+        );
+    }
 
-        Agent {
-            positions: {
-                let mut pos_vec: Vec<Vec2> = Vec::new();
-                pos_vec.push(a_vec);
-                pos_vec
-            },
-            velocities: {
-                let mut vel_vec: Vec<Vec2> = Vec::new();
-                vel_vec.push(v_vec);
-                vel_vec
-            },
-            points: vec![point_1, point_2, point_3, point_4],
-            polygon: {
-                Some(
-                    graphics::Mesh::new_polygon(
-                        ctx,
-                        graphics::DrawMode::fill(),
-                        &polygon_matrix,
-                        graphics::Color::from(CREAM),
-                    )
-                    .unwrap(),
-                )
-            },
-            agent_type,
-            dead: State::Alive,
-            kill_cooldown,
+    fn state_machine(&self) -> Option<&StateMachine> {
+        match &self.agent_type {
+            AgentType::Prey(_, params) => params.behaviour.as_ref(),
+            AgentType::Predator(_, params) => params.behaviour.as_ref(),
+        }
+    }
+
+    // Agent type with the current behavioural state's parameter overrides applied
+    pub fn effective_type(&self) -> Cow<'_, AgentType> {
+        let overrides = match self.state_machine() {
+            Some(fsm) => fsm.overrides(*self.behaviour.last().unwrap()),
+            None => return Cow::Borrowed(&self.agent_type),
+        };
+        if overrides.is_empty() {
+            return Cow::Borrowed(&self.agent_type);
+        }
+        let mut agent_type = self.agent_type.clone();
+        for (name, value) in overrides.iter() {
+            let result = match &mut agent_type {
+                AgentType::Prey(_, params) => params.set(name, *value),
+                AgentType::Predator(_, params) => params.set(name, *value),
+            };
+            result.expect("Invalid behaviour override");
+        }
+        Cow::Owned(agent_type)
+    }
+
+    // Record the behavioural state for the next time step
+    pub fn advance_behaviour(&mut self, dt: f32, perception: &Perception) {
+        let current = *self.behaviour.last().unwrap();
+        let next = self
+            .state_machine()
+            .and_then(|fsm| fsm.next(current, self.time_in_behaviour, perception));
+        match next {
+            Some(state) if state != current => {
+                self.behaviour.push(state);
+                self.time_in_behaviour = 0.0;
+            }
+            _ => {
+                self.behaviour.push(current);
+                self.time_in_behaviour += dt;
+            }
         }
     }
 
@@ -510,9 +904,28 @@ impl Agent {
         self.kill_cooldown -= dt;
     }
 
-    pub fn update(&mut self, times: &Time, acceleration: Vec2, max_vel: f32) {
-        self.update_pos(times.dt);
-        self.update_vel(times.dt, acceleration, max_vel);
+    // drift is the background flow velocity, added to the displacement but not to the velocity
+    pub fn update(
+        &mut self,
+        times: &Time,
+        acceleration: Vec2,
+        max_vel: f32,
+        kinematics: &Kinematics,
+        drift: Vec2,
+    ) {
+        self.update_pos(times.dt, drift);
+        match kinematics {
+            Kinematics::Free => self.update_vel(times.dt, acceleration, max_vel),
+            Kinematics::TurnRate { max_turn_rate, max_speed_change, min_speed } => self
+                .update_vel_turn_rate(
+                    times.dt,
+                    acceleration,
+                    max_vel,
+                    *max_turn_rate,
+                    *max_speed_change,
+                    *min_speed,
+                ),
+        }
     }
 
     fn update_vel(&mut self, dt: f32, acceleration: Vec2, max_vel: f32) {
@@ -528,8 +941,46 @@ impl Agent {
         self.velocities.push(new_vel);
     }
 
-    fn update_pos(&mut self, dt: f32) {
-        let last_vel = self.velocities.last().unwrap();
+    fn update_vel_turn_rate(
+        &mut self,
+        dt: f32,
+        acceleration: Vec2,
+        max_vel: f32,
+        max_turn_rate: f32,
+        max_speed_change: f32,
+        min_speed: f32,
+    ) {
+        let last_vel = *self.velocities.last().unwrap();
+        let desired_vel = last_vel + dt * acceleration;
+        let last_speed = last_vel.length();
+
+        // Turn towards the desired heading by no more than max_turn_rate * dt
+        let heading = if last_speed < 0.000001 {
+            if desired_vel.length() > 0.000001 {
+                desired_vel.normalize()
+            } else {
+                Vec2::ZERO
+            }
+        } else if desired_vel.length() < 0.000001 {
+            last_vel / last_speed
+        } else {
+            let max_angle = max_turn_rate * dt;
+            let angle = last_vel.angle_between(desired_vel).clamp(-max_angle, max_angle);
+            let (sin, cos) = angle.sin_cos();
+            let h = last_vel / last_speed;
+            Vec2::new(h.x * cos - h.y * sin, h.x * sin + h.y * cos)
+        };
+
+        // An agent below min_speed, e.g. stopped by a hard wall, speeds back up no faster
+        // than max_speed_change allows
+        let max_speed = (last_speed + max_speed_change * dt).min(max_vel);
+        let min_speed = (last_speed - max_speed_change * dt).max(min_speed).min(max_speed);
+        let speed = desired_vel.length().clamp(min_speed, max_speed);
+        self.velocities.push(heading * speed);
+    }
+
+    fn update_pos(&mut self, dt: f32, drift: Vec2) {
+        let last_vel = *self.velocities.last().unwrap() + drift;
         let last_pos = self.positions.last().unwrap();
         let new_pos = Vec2::new(last_vel.x * dt + last_pos.x, last_vel.y * dt + last_pos.y);
         self.positions.push(new_pos);
@@ -537,7 +988,6 @@ impl Agent {
 
     pub fn draw(
         &self,
-        ctx: &mut Context,
         canvas: &mut graphics::Canvas,
         scale: f32,
         disco_mode: &PlayState,
@@ -552,6 +1002,18 @@ impl Agent {
             _ => (),
         }
         let index = self.positions.len() - 1 - offset;
+        self.draw_at(canvas, scale, disco_mode, index, transparency);
+    }
+
+    // Draw the agent as it was at time step index, used directly when replaying a finished run
+    pub fn draw_at(
+        &self,
+        canvas: &mut graphics::Canvas,
+        scale: f32,
+        disco_mode: &PlayState,
+        index: usize,
+        transparency: f32,
+    ) {
         let last_pos = (self.positions[index]).clone();
         let angle;
         let last_vel = self.velocities[index].clone();
@@ -580,9 +1042,17 @@ impl Agent {
             },
         }
 
+        let behaviour = self.behaviour[index.min(self.behaviour.len() - 1)];
         match &self.agent_type {
+            _ if behaviour != Behaviour::Default => colour = behaviour.colour(),
+            AgentType::Prey(..) if self.informed => colour = INFORMED,
             AgentType::Prey(_, params) => {
                 colour = [last_vel_length/params.max_vel, 0.0, 1.0-(last_vel_length/params.max_vel), 1.0];
+                // Alarmed prey shade towards yellow
+                let level = self.alarm[index.min(self.alarm.len() - 1)];
+                for c in 0..3 {
+                    colour[c] += level * (ALARMED[c] - colour[c]);
+                }
             },
             AgentType::Predator(..) => colour = [0.0, 0.0, 0.0, 1.0],
         }
@@ -0,0 +1,311 @@
+use crate::boid::{Kinematics, PredParams, PreyParams};
+use crate::field::ScalarField;
+use crate::flow::FlowField;
+use crate::hunting::Search;
+use crate::model::{Parameters, BC, IC};
+use crate::refuge::{PredatorAccess, Refuge};
+use crate::scenario::{Event, Species};
+use crate::variation::Dist;
+use ggez::glam::Vec2;
+use std::cmp::Ordering;
+use std::error::Error;
+
+// Scenario files are TOML when the path ends in .toml and JSON otherwise
+impl Parameters {
+    pub fn from_file(path: &str) -> Result<Parameters, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)?;
+        let mut parameters: Parameters = if path.ends_with(".toml") {
+            serde_path_to_error::deserialize(toml::Deserializer::new(&text))
+                .map_err(|e| format!("{}: {}", path, e))?
+        } else {
+            let mut deserializer = serde_json::Deserializer::from_str(&text);
+            serde_path_to_error::deserialize(&mut deserializer)
+                .map_err(|e| format!("{}: {}", path, e))?
+        };
+        parameters
+            .validate()
+            .map_err(|e| format!("{}: {}", path, e))?;
+        // Files may list events in any order
+        parameters.schedule.sort();
+        Ok(parameters)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let text = if path.ends_with(".toml") {
+            toml::to_string_pretty(self)?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    // Values that parse but would panic or make no sense once the model runs. Every
+    // problem found is reported, one per line, named by its path in the scenario file.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        positive(&mut errors, "bound_length", self.bound_length);
+        positive(&mut errors, "times.dt", self.times.dt);
+        positive(&mut errors, "times.endtime", self.times.endtime);
+        if let BC::Soft(range) = self.boundary_condition {
+            non_negative(&mut errors, "boundary_condition.Soft", range);
+        }
+        validate_ic(&mut errors, "prey_ic", &self.prey_ic, self.num_prey);
+        validate_ic(&mut errors, "pred_ic", &self.pred_ic, self.num_pred);
+        validate_prey(&mut errors, &self.prey_params);
+        validate_pred(&mut errors, &self.pred_params);
+        let vision_radius = self.prey_params.max_radius();
+        if vision_radius <= 0.0 || vision_radius > self.bound_length {
+            errors.push(format!(
+                "prey_params: largest radius must be in (0, bound_length] (got {})",
+                vision_radius
+            ));
+        }
+
+        let mut prey = self.prey_params.clone();
+        for (i, (name, dist)) in self.prey_variation.iter().enumerate() {
+            if let Err(e) = prey.set(name, 0.0) {
+                errors.push(format!("prey_variation[{}]: {}", i, e));
+            }
+            validate_dist(&mut errors, &format!("prey_variation[{}]", i), dist);
+        }
+        let mut pred = self.pred_params.clone();
+        for (i, (name, dist)) in self.pred_variation.iter().enumerate() {
+            if let Err(e) = pred.set(name, 0.0) {
+                errors.push(format!("pred_variation[{}]: {}", i, e));
+            }
+            validate_dist(&mut errors, &format!("pred_variation[{}]", i), dist);
+        }
+        if let Some(fsm) = &self.prey_params.behaviour {
+            let mut params = self.prey_params.clone();
+            if let Err(e) = fsm.check_overrides(|name, value| params.set(name, value)) {
+                errors.push(format!("prey_params.behaviour.overrides: {}", e));
+            }
+        }
+        if let Some(fsm) = &self.pred_params.behaviour {
+            let mut params = self.pred_params.clone();
+            if let Err(e) = fsm.check_overrides(|name, value| params.set(name, value)) {
+                errors.push(format!("pred_params.behaviour.overrides: {}", e));
+            }
+        }
+        for (i, timed) in self.schedule.events.iter().enumerate() {
+            non_negative(&mut errors, &format!("schedule.events[{}].time", i), timed.time);
+            if let Event::SetParam(species, name, value) = &timed.event {
+                let result = match species {
+                    Species::Prey => prey.set(name, *value),
+                    Species::Predator => pred.set(name, *value),
+                };
+                if let Err(e) = result {
+                    errors.push(format!("schedule.events[{}]: {}", i, e));
+                }
+            }
+        }
+        if let FlowField::Grid { n, values } = &self.flow.field {
+            if *n == 0 || values.len() != n * n {
+                errors.push(format!(
+                    "flow.field.Grid: values must have n * n = {} entries (got {})",
+                    n * n,
+                    values.len()
+                ));
+            }
+        }
+        if let Err(e) = self.flow.field.load() {
+            errors.push(format!("flow.field.File: {}", e));
+        }
+        if let Some(resource) = &self.resource {
+            validate_field(&mut errors, "resource.field", &resource.field);
+            validate_field(&mut errors, "resource.capacity", &resource.capacity);
+            if resource.capacity.n != resource.field.n {
+                errors.push("resource: capacity.n must equal field.n".to_owned());
+            }
+            non_negative(&mut errors, "resource.regrowth", resource.regrowth);
+        }
+        if let Some(pheromone) = &self.pheromone {
+            validate_field(&mut errors, "pheromone.field", &pheromone.field);
+            non_negative(&mut errors, "pheromone.diffusion", pheromone.diffusion);
+            non_negative(&mut errors, "pheromone.evaporation", pheromone.evaporation);
+        }
+        for (i, refuge) in self.refuges.iter().enumerate() {
+            if let PredatorAccess::Slowed(factor) = refuge.predator_access {
+                non_negative(&mut errors, &format!("refuges[{}].predator_access.Slowed", i), factor);
+            }
+            if matches!(refuge.predator_access, PredatorAccess::Closed)
+                && ic_inside(&self.pred_ic, refuge, self.bound_length)
+            {
+                errors.push(format!("pred_ic lies wholly inside refuges[{}], which is Closed", i));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+// NaN fails every check
+fn positive(errors: &mut Vec<String>, field: &str, value: f32) {
+    if value.is_nan() || value <= 0.0 {
+        errors.push(format!("{} must be positive (got {})", field, value));
+    }
+}
+
+fn non_negative(errors: &mut Vec<String>, field: &str, value: f32) {
+    if value.is_nan() || value < 0.0 {
+        errors.push(format!("{} must not be negative (got {})", field, value));
+    }
+}
+
+fn fraction(errors: &mut Vec<String>, field: &str, value: f32) {
+    if !(0.0..=1.0).contains(&value) {
+        errors.push(format!("{} must be between 0 and 1 (got {})", field, value));
+    }
+}
+
+fn validate_ic(errors: &mut Vec<String>, field: &str, ic: &IC, n: usize) {
+    match ic {
+        IC::Rect { xmin, xmax, ymin, ymax } => {
+            if xmin.partial_cmp(xmax) != Some(Ordering::Less) {
+                errors.push(format!("{}.Rect: xmin must be less than xmax", field));
+            }
+            if ymin.partial_cmp(ymax) != Some(Ordering::Less) {
+                errors.push(format!("{}.Rect: ymin must be less than ymax", field));
+            }
+        }
+        IC::School { radius, .. } => non_negative(errors, &format!("{}.School.radius", field), *radius),
+        IC::Mill { radius, width, .. } => {
+            non_negative(errors, &format!("{}.Mill.radius", field), *radius);
+            non_negative(errors, &format!("{}.Mill.width", field), *width);
+        }
+        IC::Clusters { num_clusters, std } => {
+            if *num_clusters == 0 {
+                errors.push(format!("{}.Clusters.num_clusters must be at least 1", field));
+            }
+            non_negative(errors, &format!("{}.Clusters.std", field), *std);
+        }
+        IC::File(path) => {
+            // Spawn events read the first row even when starting with no agents
+            if let Err(e) = IC::read_file(path, n.max(1)) {
+                errors.push(format!("{}.File: {}", field, e));
+            }
+        }
+        _ => (),
+    }
+}
+
+// Whether every position the initial condition can generate is inside the refuge
+fn ic_inside(ic: &IC, refuge: &Refuge, bound_length: f32) -> bool {
+    let disc_inside = |centre: &Vec2, radius: f32| {
+        refuge.edge_distance(&(*centre * bound_length), bound_length) + radius * bound_length < 0.0
+    };
+    match ic {
+        IC::School { centre, radius, .. } => disc_inside(centre, *radius),
+        IC::Mill { centre, radius, width } => disc_inside(centre, radius + 0.5 * width),
+        // Refuges are convex so holding all four corners means holding the rectangle
+        IC::Rect { xmin, xmax, ymin, ymax } => [(xmin, ymin), (xmin, ymax), (xmax, ymin), (xmax, ymax)]
+            .iter()
+            .all(|(x, y)| refuge.contains(&(Vec2::new(**x, **y) * bound_length), bound_length)),
+        _ => false,
+    }
+}
+
+fn validate_prey(errors: &mut Vec<String>, params: &PreyParams) {
+    for (name, value) in [
+        ("align_radius", params.align_radius),
+        ("attract_radius", params.attract_radius),
+        ("repel_radius", params.repel_radius),
+        ("threat_radius", params.threat_radius),
+        ("max_acceleration", params.max_acceleration),
+        ("noise", params.noise),
+        ("position_noise", params.position_noise),
+        ("velocity_noise", params.velocity_noise),
+        ("intake_rate", params.intake_rate),
+        ("body_radius", params.body_radius),
+        ("boundary", params.boundary),
+    ] {
+        non_negative(errors, &format!("prey_params.{}", name), value);
+    }
+    positive(errors, "prey_params.max_vel", params.max_vel);
+    validate_kinematics(errors, "prey_params.kinematics", &params.kinematics);
+    fraction(errors, "prey_params.informed_fraction", params.informed_fraction);
+    if let Some(range) = params.boundary_range {
+        positive(errors, "prey_params.boundary_range", range);
+    }
+    if let Some(alarm) = &params.alarm {
+        non_negative(errors, "prey_params.alarm.radius", alarm.radius);
+        non_negative(errors, "prey_params.alarm.decay", alarm.decay);
+        fraction(errors, "prey_params.alarm.transmission", alarm.transmission);
+    }
+}
+
+fn validate_pred(errors: &mut Vec<String>, params: &PredParams) {
+    for (name, value) in [
+        ("chase_radius", params.chase_radius),
+        ("align_radius", params.align_radius),
+        ("attract_radius", params.attract_radius),
+        ("repel_radius", params.repel_radius),
+        ("memory_duration", params.memory_duration),
+        ("max_acceleration", params.max_acceleration),
+        ("noise", params.noise),
+        ("position_noise", params.position_noise),
+        ("velocity_noise", params.velocity_noise),
+        ("body_radius", params.body_radius),
+        ("boundary", params.boundary),
+        ("cooldown", params.cooldown),
+    ] {
+        non_negative(errors, &format!("pred_params.{}", name), value);
+    }
+    positive(errors, "pred_params.max_vel", params.max_vel);
+    validate_kinematics(errors, "pred_params.kinematics", &params.kinematics);
+    if let Search::Levy { mu, min_duration } = params.search {
+        if mu.is_nan() || mu <= 1.0 {
+            errors.push(format!("pred_params.search.Levy.mu must be greater than 1 (got {})", mu));
+        }
+        positive(errors, "pred_params.search.Levy.min_duration", min_duration);
+    }
+    if let Some(range) = params.boundary_range {
+        positive(errors, "pred_params.boundary_range", range);
+    }
+    if let Some(coop) = &params.cooperation {
+        non_negative(errors, "pred_params.cooperation.comm_radius", coop.comm_radius);
+    }
+}
+
+fn validate_kinematics(errors: &mut Vec<String>, field: &str, kinematics: &Kinematics) {
+    if let Kinematics::TurnRate { max_turn_rate, max_speed_change, min_speed } = kinematics {
+        non_negative(errors, &format!("{}.TurnRate.max_turn_rate", field), *max_turn_rate);
+        non_negative(errors, &format!("{}.TurnRate.max_speed_change", field), *max_speed_change);
+        non_negative(errors, &format!("{}.TurnRate.min_speed", field), *min_speed);
+    }
+}
+
+fn validate_field(errors: &mut Vec<String>, field: &str, scalar: &ScalarField) {
+    if scalar.n == 0 {
+        errors.push(format!("{}.n must be at least 1", field));
+    } else if scalar.values.len() != scalar.n * scalar.n {
+        errors.push(format!(
+            "{}: values must have n * n = {} entries (got {})",
+            field,
+            scalar.n * scalar.n,
+            scalar.values.len()
+        ));
+    }
+}
+
+fn validate_dist(errors: &mut Vec<String>, field: &str, dist: &Dist) {
+    match dist {
+        Dist::Normal { std, .. } => non_negative(errors, &format!("{}.Normal.std", field), *std),
+        Dist::Mixture(components) => {
+            if components.is_empty() {
+                errors.push(format!("{}.Mixture must have at least one component", field));
+            }
+            for (i, (weight, component)) in components.iter().enumerate() {
+                let field = format!("{}.Mixture[{}]", field, i);
+                non_negative(errors, &format!("{}.weight", field), *weight);
+                validate_dist(errors, &field, component);
+            }
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,214 @@
+use ggez::glam::Vec2;
+use ggez::graphics::{self, Color, DrawMode, Mesh, MeshBuilder, Rect};
+use ggez::Context;
+use serde::{Deserialize, Serialize};
+
+// Scalar quantity on an n x n grid of square cells covering the arena, row-major from (0, 0)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScalarField {
+    pub n: usize,
+    pub values: Vec<f32>,
+}
+
+impl ScalarField {
+    pub fn new(n: usize, initial: f32) -> ScalarField {
+        ScalarField {
+            n,
+            values: vec![initial; n * n],
+        }
+    }
+
+    fn cell(&self, pos: &Vec2, bound_length: f32) -> (usize, usize) {
+        let size = bound_length / self.n as f32;
+        let i = ((pos.x / size).max(0.0) as usize).min(self.n - 1);
+        let j = ((pos.y / size).max(0.0) as usize).min(self.n - 1);
+        (i, j)
+    }
+
+    pub fn value(&self, pos: &Vec2, bound_length: f32) -> f32 {
+        let (i, j) = self.cell(pos, bound_length);
+        self.values[j * self.n + i]
+    }
+
+    // Central differences between neighbouring cells, one-sided at the walls unless the
+    // arena is periodic
+    pub fn gradient(&self, pos: &Vec2, bound_length: f32, periodic: bool) -> Vec2 {
+        let n = self.n;
+        let size = bound_length / n as f32;
+        let (i, j) = self.cell(pos, bound_length);
+        let at = |i: usize, j: usize| self.values[j * n + i];
+        if periodic {
+            let (il, ir) = ((i + n - 1) % n, (i + 1) % n);
+            let (jd, ju) = ((j + n - 1) % n, (j + 1) % n);
+            let dx = (at(ir, j) - at(il, j)) / (2.0 * size);
+            let dy = (at(i, ju) - at(i, jd)) / (2.0 * size);
+            return Vec2::new(dx, dy);
+        }
+        let (il, ir) = (i.saturating_sub(1), (i + 1).min(n - 1));
+        let (jd, ju) = (j.saturating_sub(1), (j + 1).min(n - 1));
+        let dx = if ir > il {
+            (at(ir, j) - at(il, j)) / ((ir - il) as f32 * size)
+        } else {
+            0.0
+        };
+        let dy = if ju > jd {
+            (at(i, ju) - at(i, jd)) / ((ju - jd) as f32 * size)
+        } else {
+            0.0
+        };
+        Vec2::new(dx, dy)
+    }
+
+    pub fn add(&mut self, pos: &Vec2, bound_length: f32, amount: f32) {
+        let (i, j) = self.cell(pos, bound_length);
+        self.values[j * self.n + i] += amount;
+    }
+
+    // Remove up to amount from the cell at pos, returning what was removed
+    pub fn take(&mut self, pos: &Vec2, bound_length: f32, amount: f32) -> f32 {
+        let (i, j) = self.cell(pos, bound_length);
+        let cell = &mut self.values[j * self.n + i];
+        let taken = amount.min(*cell).max(0.0);
+        *cell -= taken;
+        taken
+    }
+
+    pub fn total(&self) -> f32 {
+        self.values.iter().sum()
+    }
+
+    // Shade each cell by its value relative to max
+    pub fn draw(
+        &self,
+        ctx: &mut Context,
+        canvas: &mut graphics::Canvas,
+        bound_length: f32,
+        scale: f32,
+        colour: [f32; 4],
+        max: f32,
+    ) {
+        let size = bound_length / self.n as f32 * scale;
+        let mut builder = MeshBuilder::new();
+        let mut any = false;
+        for j in 0..self.n {
+            for i in 0..self.n {
+                let level = (self.values[j * self.n + i] / max).clamp(0.0, 1.0);
+                if level <= 0.0 {
+                    continue;
+                }
+                let rect = Rect::new(i as f32 * size, j as f32 * size, size, size);
+                let colour = Color::new(colour[0], colour[1], colour[2], colour[3] * level);
+                builder
+                    .rectangle(DrawMode::fill(), rect, colour)
+                    .expect("Could not build field mesh");
+                any = true;
+            }
+        }
+        if any {
+            let mesh = Mesh::from_data(ctx, builder.build());
+            canvas.draw(&mesh, graphics::DrawParam::new());
+        }
+    }
+}
+
+// Food that prey graze from, regrowing towards a per-cell capacity at a rate proportional
+// to what is missing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource {
+    pub field: ScalarField,
+    pub capacity: ScalarField,
+    // Fraction of the missing resource restored per second
+    pub regrowth: f32,
+}
+
+impl Resource {
+    // Circular patches of (centre, radius, capacity), positions as fractions of bound_length.
+    // Patches start full.
+    pub fn patches(n: usize, patches: &[(Vec2, f32, f32)], regrowth: f32) -> Resource {
+        let mut capacity = ScalarField::new(n, 0.0);
+        for j in 0..n {
+            for i in 0..n {
+                let centre = Vec2::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                for (patch_centre, radius, patch_capacity) in patches.iter() {
+                    if (centre - *patch_centre).length() < *radius {
+                        capacity.values[j * n + i] += patch_capacity;
+                    }
+                }
+            }
+        }
+        Resource {
+            field: capacity.clone(),
+            capacity,
+            regrowth,
+        }
+    }
+
+    pub fn regrow(&mut self, dt: f32) {
+        for (value, capacity) in self.field.values.iter_mut().zip(self.capacity.values.iter()) {
+            // Large regrowth * dt would step past capacity
+            *value = (*value + self.regrowth * (capacity - *value) * dt).min(*capacity);
+        }
+    }
+
+    pub fn max_capacity(&self) -> f32 {
+        self.capacity.values.iter().cloned().fold(0.0, f32::max)
+    }
+}
+
+// Diffusing, evaporating trail that agents deposit into and steer along. There is one
+// field for both species, so each species' pheromone_response follows every trail laid,
+// including the other species'.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pheromone {
+    pub field: ScalarField,
+    // Diffusion coefficient in length^2 per second
+    pub diffusion: f32,
+    // Fraction lost per second
+    pub evaporation: f32,
+}
+
+impl Pheromone {
+    pub fn new(n: usize, diffusion: f32, evaporation: f32) -> Pheromone {
+        Pheromone {
+            field: ScalarField::new(n, 0.0),
+            diffusion,
+            evaporation,
+        }
+    }
+
+    // Explicit diffusion step, with no flux through the walls or wrapping round a periodic
+    // arena, then evaporation
+    pub fn update(&mut self, bound_length: f32, dt: f32, periodic: bool) {
+        let n = self.field.n;
+        let h = bound_length / n as f32;
+        // Keep the explicit scheme stable
+        let rate = (self.diffusion * dt / (h * h)).min(0.25);
+        let old = self.field.values.clone();
+        let at = |i: usize, j: usize| old[j * n + i];
+        for j in 0..n {
+            for i in 0..n {
+                let centre = at(i, j);
+                let mut laplacian = 0.0;
+                if periodic {
+                    laplacian += at((i + n - 1) % n, j) + at((i + 1) % n, j) - 2.0 * centre;
+                    laplacian += at(i, (j + n - 1) % n) + at(i, (j + 1) % n) - 2.0 * centre;
+                } else {
+                    if i > 0 {
+                        laplacian += at(i - 1, j) - centre;
+                    }
+                    if i + 1 < n {
+                        laplacian += at(i + 1, j) - centre;
+                    }
+                    if j > 0 {
+                        laplacian += at(i, j - 1) - centre;
+                    }
+                    if j + 1 < n {
+                        laplacian += at(i, j + 1) - centre;
+                    }
+                }
+                self.field.values[j * n + i] =
+                    (centre + rate * laplacian) * (-self.evaporation * dt).exp();
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use ggez::glam::Vec2;
+use std::fs;
+use serde::{Deserialize, Serialize};
+
+// Background flow velocity as a function of position. Positions and lengths are fractions
+// of bound_length, speeds are absolute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FlowField {
+    Still,
+    Uniform(Vec2),
+    // Horizontal flow varying linearly from -max_speed at y = 0 to max_speed at y = bound_length
+    Shear { max_speed: f32 },
+    // Rankine vortex: solid rotation inside the core, decaying as 1/r outside.
+    // Positive strength is anticlockwise.
+    Vortex { centre: Vec2, strength: f32, core: f32 },
+    // n x n piecewise constant cells in row-major order starting at (0, 0)
+    Grid { n: usize, values: Vec<Vec2> },
+    // Grid read from a CSV by FlowField::from_file when the model is built
+    File(String),
+}
+
+impl FlowField {
+    // CSV with one `vx,vy` row per cell, n*n rows in row-major order
+    pub fn from_file(path: &str) -> Result<FlowField, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let mut values = Vec::new();
+        for (line_number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row: Vec<f32> = line
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{} line {}: {}", path, line_number + 1, e))?;
+            if row.len() < 2 {
+                return Err(format!("{} line {} needs vx,vy", path, line_number + 1));
+            }
+            values.push(Vec2::new(row[0], row[1]));
+        }
+        let n = (values.len() as f32).sqrt().round() as usize;
+        if n == 0 || n * n != values.len() {
+            return Err(format!("{} must have a square number of rows, got {}", path, values.len()));
+        }
+        Ok(FlowField::Grid { n, values })
+    }
+
+    // Replaces File with the Grid it holds
+    pub fn load(&self) -> Result<FlowField, String> {
+        match self {
+            FlowField::File(path) => FlowField::from_file(path),
+            field => Ok(field.clone()),
+        }
+    }
+
+    pub fn velocity(&self, pos: &Vec2, bound_length: f32) -> Vec2 {
+        match self {
+            FlowField::Still => Vec2::ZERO,
+            FlowField::Uniform(v) => *v,
+            FlowField::Shear { max_speed } => {
+                Vec2::new(max_speed * (2.0 * pos.y / bound_length - 1.0), 0.0)
+            }
+            FlowField::Vortex { centre, strength, core } => {
+                let r_vec = *pos - *centre * bound_length;
+                let r = r_vec.length();
+                let core = core * bound_length;
+                if r < 0.0000001 {
+                    return Vec2::ZERO;
+                }
+                let speed = if r < core {
+                    strength * r / core
+                } else {
+                    strength * core / r
+                };
+                speed * r_vec.perp() / r
+            }
+            FlowField::Grid { n, values } => {
+                let cell = bound_length / *n as f32;
+                let i = ((pos.x / cell) as usize).min(n - 1);
+                let j = ((pos.y / cell) as usize).min(n - 1);
+                values[j * n + i]
+            }
+            // Loaded into a Grid by Model::from
+            FlowField::File(_) => Vec2::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Flow {
+    pub field: FlowField,
+    // Fraction of the flow velocity added to each agent's displacement
+    pub advection: f32,
+    // Weight of the flow direction in the preferred heading used by current_direction
+    pub heading_bias: f32,
+}
+
+impl Default for Flow {
+    fn default() -> Flow {
+        Flow::still()
+    }
+}
+
+impl Flow {
+    pub fn still() -> Flow {
+        Flow {
+            field: FlowField::Still,
+            advection: 0.0,
+            heading_bias: 0.0,
+        }
+    }
+
+    pub fn drift(&self, pos: &Vec2, bound_length: f32) -> Vec2 {
+        if self.advection == 0.0 {
+            return Vec2::ZERO;
+        }
+        self.advection * self.field.velocity(pos, bound_length)
+    }
+
+    // Unit vector along the current heading, turned towards the flow by heading_bias
+    pub fn preferred_heading(&self, pos: &Vec2, vel: &Vec2, bound_length: f32) -> Vec2 {
+        let mut heading = vel.normalize_or_zero();
+        if self.heading_bias != 0.0 {
+            heading += self.heading_bias * self.field.velocity(pos, bound_length).normalize_or_zero();
+        }
+        heading.normalize_or_zero()
+    }
+}
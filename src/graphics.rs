@@ -17,6 +17,10 @@ pub const DRED: [f32; 4] = [120.0 / 255.0, 0.0, 0.0, 1.0];
 pub const LRED: [f32; 4] = [193.0 / 255.0, 18.0 / 255.0, 31.0 / 255.0, 1.0];
 //pub const CREAM: [f32; 4] = [253.0 / 255.0, 240.0 / 255.0, 213.0 / 255.0, 1.0];
 pub const CREAM: [f32; 4] = [1.0, 1.0,1.0,1.0];
+pub const INFORMED: [f32; 4] = [0.1, 0.8, 0.2, 1.0];
+pub const ALARMED: [f32; 4] = [1.0, 0.9, 0.0, 1.0];
+pub const RESOURCE: [f32; 4] = [0.2, 0.6, 0.2, 0.5];
+pub const PHEROMONE: [f32; 4] = [0.6, 0.2, 0.7, 0.5];
 //pub const DBLUE: [f32; 4] = [0.0, 48.0 / 255.0, 73.0 / 255.0, 1.0];
 pub const DBLUE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
 pub const LBLUE: [f32; 4] = [102.0, 155.0 / 255.0, 188.0 / 255.0, 1.0];
@@ -81,6 +85,7 @@ pub struct GUIParameters {
     pub num_pred: String,
     pub prey_params: GUIPreyParams,
     pub pred_params: GUIPredParams,
+    pub checkpoint: String,
 }
 
 impl GUIPreyParams {
@@ -96,7 +101,7 @@ impl GUIPreyParams {
             predator_repulsion: "10".to_owned(),
             max_acceleration: "1.0".to_owned(),
             max_vel: "1.0".to_owned(),
-            boundary: "20".to_owned(),
+            boundary: "1".to_owned(),
         }
     }
 }
@@ -114,7 +119,7 @@ impl GUIPredParams {
             predator_repulsion: "0".to_owned(),
             max_acceleration: "1.0".to_owned(),
             max_vel: "1.0".to_owned(),
-            boundary: "10".to_owned(),
+            boundary: "1".to_owned(),
         }
     }
 }
@@ -126,6 +131,7 @@ impl GUIParameters {
             num_pred: "100".to_owned(),
             prey_params: GUIPreyParams::new(),
             pred_params: GUIPredParams::new(),
+            checkpoint: "checkpoint.json".to_owned(),
         }
     }
 }
@@ -139,6 +145,8 @@ struct MainState {
     assets: Assets,
     gui: Gui,
     parameters: GUIParameters,
+    // Time step shown when playing back a finished run instead of simulating
+    replay: Option<usize>,
 }
 
 impl MainState {
@@ -157,6 +165,7 @@ impl MainState {
             assets: Assets::new(ctx)?,
             gui: Gui::new(ctx),
             parameters: GUIParameters::new(),
+            replay: None,
         };
         Ok(s)
     }
@@ -176,9 +185,42 @@ impl MainState {
             assets: Assets::new(ctx)?,
             gui: Gui::new(ctx),
             parameters: GUIParameters::new(),
+            replay: None,
         };
         Ok(s)
     }
+
+    // Starts paused so the loaded state can be inspected
+    fn from_checkpoint(ctx: &mut ggez::context::Context, path: &str) -> GameResult<MainState> {
+        ctx.gfx.add_font(
+            "LiberationMono",
+            graphics::FontData::from_path(ctx, "/LiberationMono-Regular.ttf")?,
+        );
+
+        let model = Model::graphical_from_checkpoint(ctx, path)
+            .map_err(|e| ggez::GameError::CustomError(e.to_string()))?;
+        let mut parameters = GUIParameters::new();
+        parameters.checkpoint = path.to_owned();
+        let s = MainState {
+            frames: 0,
+            model,
+            play_state: PlayState::paused,
+            disco_mode: PlayState::paused,
+            trail: PlayState::paused,
+            assets: Assets::new(ctx)?,
+            gui: Gui::new(ctx),
+            parameters,
+            replay: None,
+        };
+        Ok(s)
+    }
+
+    fn from_replay(ctx: &mut ggez::context::Context, path: &str) -> GameResult<MainState> {
+        let mut s = MainState::from_checkpoint(ctx, path)?;
+        s.replay = Some(0);
+        s.play_state = PlayState::play;
+        Ok(s)
+    }
 }
 
 // Then we implement the `ggez:event::EventHandler` trait on it, which
@@ -219,7 +261,7 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     ));
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Current velocity: ");
+                    ui.label("Heading persistence: ");
                     ui.add(egui::TextEdit::singleline(
                         &mut self.parameters.prey_params.current_direction,
                     ));
@@ -267,7 +309,7 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     ));
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Boundary: ");
+                    ui.label("Boundary avoidance: ");
                     ui.add(egui::TextEdit::singleline(
                         &mut self.parameters.prey_params.boundary,
                     ));
@@ -288,7 +330,7 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     ));
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Current velocity: ");
+                    ui.label("Heading persistence: ");
                     ui.add(egui::TextEdit::singleline(
                         &mut self.parameters.pred_params.current_direction,
                     ));
@@ -336,7 +378,7 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     ));
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Boundary: ");
+                    ui.label("Boundary avoidance: ");
                     ui.add(egui::TextEdit::singleline(
                         &mut self.parameters.pred_params.boundary,
                     ));
@@ -344,6 +386,26 @@ impl event::EventHandler<ggez::GameError> for MainState {
             });
             if ui.button("Set Parameters").clicked() {
                 self.model = Model::from_parameters(ctx, &mut self.parameters);
+                self.replay = None;
+            }
+            ui.horizontal(|ui| {
+                ui.label("Checkpoint: ");
+                ui.add(egui::TextEdit::singleline(&mut self.parameters.checkpoint));
+            });
+            if ui.button("Save Checkpoint").clicked() {
+                if let Err(e) = self.model.save_checkpoint(&self.parameters.checkpoint) {
+                    eprintln!("Could not save checkpoint: {}", e);
+                }
+            }
+            if ui.button("Load Checkpoint").clicked() {
+                match Model::graphical_from_checkpoint(ctx, &self.parameters.checkpoint) {
+                    Ok(model) => {
+                        self.model = model;
+                        self.play_state = PlayState::paused;
+                        self.replay = None;
+                    }
+                    Err(e) => eprintln!("Could not load checkpoint: {}", e),
+                }
             }
             if ui.button("Disco Mode").clicked() {
                 match self.disco_mode {
@@ -361,9 +423,14 @@ impl event::EventHandler<ggez::GameError> for MainState {
         self.gui.update(ctx);
 
         // Pause logic
-        match self.play_state {
-            PlayState::paused => (),
-            PlayState::play => self.model.step(),
+        match (&self.play_state, self.replay) {
+            (PlayState::paused, _) => (),
+            (PlayState::play, Some(t)) => {
+                self.replay = Some((t + 1).min(self.model.times.current_index));
+            }
+            (PlayState::play, None) => {
+                self.model.step();
+            }
         }
         Ok(())
     }
@@ -371,15 +438,19 @@ impl event::EventHandler<ggez::GameError> for MainState {
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let mut canvas = graphics::Canvas::from_frame(ctx, graphics::Color::from(DBLUE));
 
-        match self.play_state {
-            PlayState::paused => match self.trail {
-                PlayState::paused => self.model.draw(ctx, &mut canvas, &self.disco_mode),
-                PlayState::play => self.model.draw_trail(ctx, &mut canvas, &self.disco_mode),
-            },
-            PlayState::play => match self.trail {
-                PlayState::paused => self.model.draw(ctx, &mut canvas, &self.disco_mode),
-                PlayState::play => self.model.draw_trail(ctx, &mut canvas, &self.disco_mode),
-            },
+        if let Some(t) = self.replay {
+            self.model.draw_replay(ctx, &mut canvas, &self.disco_mode, t);
+        } else {
+            match self.play_state {
+                PlayState::paused => match self.trail {
+                    PlayState::paused => self.model.draw(ctx, &mut canvas, &self.disco_mode),
+                    PlayState::play => self.model.draw_trail(ctx, &mut canvas, &self.disco_mode),
+                },
+                PlayState::play => match self.trail {
+                    PlayState::paused => self.model.draw(ctx, &mut canvas, &self.disco_mode),
+                    PlayState::play => self.model.draw_trail(ctx, &mut canvas, &self.disco_mode),
+                },
+            }
         }
 
         //canvas.draw(&self.gui, graphics::DrawParam::default().dest(Vec2::ZERO));
@@ -450,29 +521,24 @@ impl event::EventHandler<ggez::GameError> for MainState {
 // do the work of creating our MainState and running our game.
 // * Then, just call `game.run()` which runs the `Game` mainloop.
 pub fn start_game() -> GameResult {
-    // We add the CARGO_MANIFEST_DIR/resources to the resource paths
-    // so that ggez will look in our cargo project directory for files.
-    let resource_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        let mut path = path::PathBuf::from(manifest_dir);
-        path.push("resources");
-        path
-    } else {
-        path::PathBuf::from("./resources")
-    };
+    run_window(MainState::new)
+}
 
-    let cb = ggez::ContextBuilder::new("boids", "ggez").add_resource_path(resource_dir);
-    let (mut ctx, event_loop) = cb.build()?;
+pub fn start_game_from_parameters(params: &Parameters) -> GameResult {
+    run_window(|ctx| MainState::from_params(ctx, params))
+}
 
-    let mut w_pos = ctx.gfx.window_position().unwrap();
-    w_pos.x = (1920 - WINDOW_WIDTH as i32) / 2;
-    w_pos.y = (1200 - WINDOW_HEIGHT as i32) / 2;
-    ctx.gfx.set_window_position(w_pos);
-    ctx.gfx.set_drawable_size(WINDOW_WIDTH, WINDOW_HEIGHT)?;
-    let state = MainState::new(&mut ctx)?;
-    event::run(ctx, event_loop, state)
+pub fn start_game_from_checkpoint(path: &str) -> GameResult {
+    run_window(|ctx| MainState::from_checkpoint(ctx, path))
 }
 
-pub fn start_game_from_parameters(params: &Parameters) -> GameResult {
+// Play back a run saved with Model::save_checkpoint, space pauses
+pub fn start_replay(path: &str) -> GameResult {
+    run_window(|ctx| MainState::from_replay(ctx, path))
+}
+
+// Build the context and centred window, then run the state made by make_state
+fn run_window<F: FnOnce(&mut Context) -> GameResult<MainState>>(make_state: F) -> GameResult {
     // We add the CARGO_MANIFEST_DIR/resources to the resource paths
     // so that ggez will look in our cargo project directory for files.
     let resource_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
@@ -491,6 +557,6 @@ pub fn start_game_from_parameters(params: &Parameters) -> GameResult {
     w_pos.y = (1200 - WINDOW_HEIGHT as i32) / 2;
     ctx.gfx.set_window_position(w_pos);
     ctx.gfx.set_drawable_size(WINDOW_WIDTH, WINDOW_HEIGHT)?;
-    let state = MainState::from_params(&mut ctx, params)?;
+    let state = make_state(&mut ctx)?;
     event::run(ctx, event_loop, state)
 }
@@ -1,6 +1,8 @@
 use crate::boid::Agent;
 use ggez::glam::Vec2;
+use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize)]
 pub struct Cell {
     pub ymin: f32,
     pub ymax: f32,
@@ -10,6 +12,7 @@ pub struct Cell {
     pub agent_indices: Vec<usize>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Grid {
     r_hat: f32,
     pub num_cells: usize,
@@ -60,8 +63,46 @@ impl Grid {
         (i, j)
     }
 
+    // Cells that can contain agents within `radius` of a point in cell (c_i, c_j),
+    // wrapping around the domain and visiting each cell at most once
+    pub fn neighbour_cells(&self, c_i: usize, c_j: usize, radius: f32) -> Vec<(usize, usize)> {
+        let reach = ((radius / self.r_hat).ceil() as usize).max(1);
+        let mut cells = Vec::new();
+        if 2 * reach + 1 >= self.num_cells {
+            for i in 0..self.num_cells {
+                for j in 0..self.num_cells {
+                    cells.push((i, j));
+                }
+            }
+            return cells;
+        }
+        for n in 0..2 * reach + 1 {
+            for m in 0..2 * reach + 1 {
+                let index_i = (c_i + n + self.num_cells - reach) % self.num_cells;
+                let index_j = (c_j + m + self.num_cells - reach) % self.num_cells;
+                cells.push((index_i, index_j));
+            }
+        }
+        cells
+    }
+
     pub fn push_agent(&mut self, position: &Vec2, index: usize) {
         let (i, j) = self.cell_finder(position);
         self.cells[i][j].push_agent(index);
     }
+
+    // Remove an agent from its cell and shift down the indices of the agents
+    // stored after it in the agent vector
+    pub fn remove_agent(&mut self, index: usize) {
+        for row in self.cells.iter_mut() {
+            for cell in row.iter_mut() {
+                cell.agent_indices.retain(|&a| a != index);
+                for a in cell.agent_indices.iter_mut() {
+                    if *a > index {
+                        *a -= 1;
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,123 @@
+use ggez::glam::Vec2;
+use rand::Rng;
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+
+// How a predator moves when it neither sees prey nor remembers where they were
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Search {
+    // No search force, predators drift with noise and predator-predator terms
+    Wander,
+    // Heading turns at turn_rate / (1 + growth * t) rad/s, tracing an expanding spiral
+    Spiral { turn_rate: f32, growth: f32 },
+    // Straight legs in random directions with Pareto distributed durations,
+    // P(T > t) = (min_duration / t)^(mu - 1)
+    Levy { mu: f32, min_duration: f32 },
+}
+
+// Explicit group hunting between predators within comm_radius of each other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cooperation {
+    // Predators share last-seen prey positions and claimed targets within this distance
+    pub comm_radius: f32,
+    // Strength of the tangential force spreading partners around the shared target
+    pub flank_weight: f32,
+    // Chase the nearest visible prey not already claimed by a partner
+    pub split_targets: bool,
+}
+
+// Per-predator hunting state carried between time steps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HuntState {
+    // Position where prey were last seen and the time since
+    pub last_seen: Option<Vec2>,
+    pub since_seen: f32,
+    pub search_heading: Vec2,
+    pub search_time: f32,
+    pub leg_remaining: f32,
+    // Id of the prey claimed when splitting targets
+    pub target: Option<usize>,
+}
+
+impl Default for HuntState {
+    fn default() -> HuntState {
+        HuntState::new()
+    }
+}
+
+impl HuntState {
+    pub fn new() -> HuntState {
+        HuntState {
+            last_seen: None,
+            since_seen: 0.0,
+            search_heading: Vec2::ZERO,
+            search_time: 0.0,
+            leg_remaining: 0.0,
+            target: None,
+        }
+    }
+
+    pub fn see(&mut self, position: Vec2) {
+        self.last_seen = Some(position);
+        self.since_seen = 0.0;
+        // The next search starts again from the predator's heading
+        self.search_heading = Vec2::ZERO;
+        self.search_time = 0.0;
+    }
+
+    // Adopt a partner's memory of prey if it is fresher than our own
+    pub fn share(&mut self, partner: &HuntState) {
+        if partner.last_seen.is_some()
+            && (self.last_seen.is_none() || partner.since_seen < self.since_seen)
+        {
+            self.last_seen = partner.last_seen;
+            self.since_seen = partner.since_seen;
+        }
+    }
+
+    // Remembered prey position, forgotten once it is older than duration
+    pub fn recall(&mut self, dt: f32, duration: f32) -> Option<Vec2> {
+        self.since_seen += dt;
+        if self.since_seen > duration {
+            self.last_seen = None;
+        }
+        self.last_seen
+    }
+
+    // Unit search direction for this step, starting from the agent's heading
+    pub fn search_direction<R: Rng>(
+        &mut self,
+        search: &Search,
+        heading: Vec2,
+        dt: f32,
+        rng: &mut R,
+    ) -> Vec2 {
+        if self.search_heading == Vec2::ZERO {
+            self.search_heading = heading.normalize_or_zero();
+            if self.search_heading == Vec2::ZERO {
+                self.search_heading = Vec2::X;
+            }
+        }
+        match search {
+            Search::Wander => return Vec2::ZERO,
+            Search::Spiral { turn_rate, growth } => {
+                let angle = turn_rate / (1.0 + growth * self.search_time) * dt;
+                let (sin, cos) = angle.sin_cos();
+                let h = self.search_heading;
+                self.search_heading = Vec2::new(h.x * cos - h.y * sin, h.x * sin + h.y * cos);
+            }
+            Search::Levy { mu, min_duration } => {
+                self.leg_remaining -= dt;
+                if self.leg_remaining <= 0.0 {
+                    let angle: f32 = rng.gen_range(0.0..2.0 * PI);
+                    self.search_heading = Vec2::new(angle.cos(), angle.sin());
+                    let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+                    self.leg_remaining = min_duration * u.powf(-1.0 / (mu - 1.0));
+                }
+            }
+        }
+        self.search_time += dt;
+        self.search_heading
+    }
+}
@@ -0,0 +1,52 @@
+use ggez::glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+// Distance weighting for a social force. Positional terms add weight(r) times the unit
+// vector towards the neighbour, so InversePower(1.0) is a 1/r force and InversePower(-1.0)
+// a linear spring. Alignment adds weight(r) times the velocity difference.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Kernel {
+    Uniform,
+    InversePower(f32),
+    // Standard deviation
+    Gaussian(f32),
+    // Decay length
+    Exponential(f32),
+    // (outer radius, weight) pairs in increasing radius, zero beyond the last zone
+    Zones(Vec<(f32, f32)>),
+}
+
+impl Kernel {
+    pub fn weight(&self, r: f32) -> f32 {
+        match self {
+            Kernel::Uniform => 1.0,
+            Kernel::InversePower(p) => r.powf(-p),
+            Kernel::Gaussian(sigma) => (-r * r / (2.0 * sigma * sigma)).exp(),
+            Kernel::Exponential(length) => (-r / length).exp(),
+            Kernel::Zones(zones) => {
+                for (outer, weight) in zones.iter() {
+                    if r < *outer {
+                        return *weight;
+                    }
+                }
+                0.0
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Normalisation {
+    // Divide by the number of neighbours contributing
+    Mean,
+    Sum,
+}
+
+impl Normalisation {
+    pub fn apply(&self, total: Vec2, count: i32) -> Vec2 {
+        match self {
+            Normalisation::Mean if count > 0 => total / count as f32,
+            _ => total,
+        }
+    }
+}
@@ -1,7 +1,18 @@
+pub mod behaviour;
 pub mod boid;
+pub mod config;
+pub mod field;
+pub mod flow;
 pub mod graphics;
 pub mod grid;
+pub mod hunting;
+pub mod kernel;
 pub mod model;
+pub mod observer;
 pub mod plot;
+pub mod refuge;
+pub mod scenario;
+pub mod stopping;
 pub mod testing;
+pub mod variation;
 pub mod parameter_search;
@@ -2,63 +2,355 @@ use boids::boid::{PredParams, PreyParams};
 use boids::graphics;
 use boids::model::{Model, Parameters, Time, BC};
 use boids::plot::*;
-use boids::parameter_search::*;
+use boids::parameter_search::{co_evolve, mean, std_deviation, Foraging, Objective};
 use boids::testing;
 use ggez::glam::Vec2;
 use std::time::Instant;
 use cmaes::DVector;
-
-// TODO
-// FIX VISION RATIO
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::error::Error;
 
 fn estimated_running_time(dt: f32, endtime: f32, num_iterations: f32) -> f32 {
     (120.71 / (1000.0 * 60.0 * 50.0)) * (1.0 / dt) * endtime * num_iterations
 }
 
+#[derive(Parser)]
+#[command(name = "boids", about = "Predator-prey collective motion simulations")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+// Parameters come from the scenario file when one is given, then any values set on the
+// command line override it
+#[derive(Args)]
+struct ScenarioArgs {
+    /// TOML or JSON scenario file, fields it leaves out take their defaults
+    #[arg(short, long)]
+    scenario: Option<String>,
+    #[arg(long)]
+    num_prey: Option<usize>,
+    #[arg(long)]
+    num_pred: Option<usize>,
+    #[arg(long)]
+    bound_length: Option<f32>,
+    #[arg(long)]
+    dt: Option<f32>,
+    #[arg(long)]
+    endtime: Option<f32>,
+    /// Prey parameter as NAME=VALUE, e.g. --prey noise=0.1, may be repeated
+    #[arg(long, value_parser = parse_assignment)]
+    prey: Vec<(String, f32)>,
+    /// Predator parameter as NAME=VALUE, may be repeated
+    #[arg(long, value_parser = parse_assignment)]
+    pred: Vec<(String, f32)>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a scenario without graphics and write the scenario, trajectory and kills to a directory
+    Run {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+        #[arg(short, long, default_value = "output")]
+        output: String,
+    },
+    /// Open the viewer on a scenario, or on a checkpoint for inspection
+    View {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+        #[arg(long)]
+        checkpoint: Option<String>,
+        /// Co-evolution results to view, read from json/results_<RESULT>.json
+        #[arg(long)]
+        result: Option<String>,
+        /// Predator and prey round to take from the results
+        #[arg(long, num_args = 2, default_values_t = [0, 0])]
+        index: Vec<usize>,
+    },
+    /// Run copies of a scenario and report statistics across them
+    Replicate {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+        #[arg(short = 'n', long, default_value_t = 30)]
+        runs: usize,
+        /// CSV file for the results of each run
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Co-evolve prey and predator behaviour with CMA-ES
+    Optimise(OptimiseArgs),
+    /// Compute metrics from a trajectory saved by run
+    Analyse {
+        trajectory: String,
+        /// Directory for per-step CSV output
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Play back a trajectory saved by run
+    Replay { trajectory: String },
+}
+
+// The search settings come from the scenario file when one is given, otherwise from the
+// defaults below, then any values set on the command line override them
+#[derive(Args)]
+struct OptimiseArgs {
+    /// Scenario file supplying bound_length, num_pred, the predator max_acceleration and
+    /// max_vel, and the resource and prey forage_weight/intake_rate
+    #[arg(short, long, alias = "forage-scenario")]
+    scenario: Option<String>,
+    /// Defaults to 3.0 without a scenario
+    #[arg(long)]
+    pred_max_acceleration: Option<f32>,
+    /// Defaults to 1.25 without a scenario
+    #[arg(long)]
+    pred_max_vel: Option<f32>,
+    /// Defaults to 10.0 without a scenario
+    #[arg(long)]
+    bound_length: Option<f32>,
+    /// Defaults to 3 without a scenario
+    #[arg(long)]
+    num_pred: Option<usize>,
+    /// Prey then predator optimisations per round
+    #[arg(long, default_value_t = 4)]
+    rounds: usize,
+    /// What the prey minimise
+    #[arg(long, value_enum, default_value_t = ObjectiveArg::Deaths)]
+    objective: ObjectiveArg,
+    /// Weight of mean intake against deaths for deaths-and-intake
+    #[arg(long, default_value_t = 1.0)]
+    intake_weight: f32,
+    #[arg(short, long, default_value = "results.json")]
+    output: String,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ObjectiveArg {
+    Deaths,
+    Intake,
+    DeathsAndIntake,
+}
+
+fn parse_assignment(s: &str) -> Result<(String, f32), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or(format!("expected NAME=VALUE, got {}", s))?;
+    let value = value
+        .parse::<f32>()
+        .map_err(|e| format!("{}: {}", name, e))?;
+    Ok((name.to_owned(), value))
+}
+
+impl ScenarioArgs {
+    fn parameters(&self) -> Result<Parameters, Box<dyn Error>> {
+        let mut params = match &self.scenario {
+            Some(path) => Parameters::from_file(path)?,
+            None => Parameters::default(),
+        };
+        if let Some(n) = self.num_prey {
+            params.num_prey = n;
+        }
+        if let Some(n) = self.num_pred {
+            params.num_pred = n;
+        }
+        if let Some(length) = self.bound_length {
+            params.bound_length = length;
+        }
+        if self.dt.is_some() || self.endtime.is_some() {
+            params.times = Time::new(
+                self.dt.unwrap_or(params.times.dt),
+                self.endtime.unwrap_or(params.times.endtime),
+            );
+        }
+        for (name, value) in self.prey.iter() {
+            params.prey_params.set(name, *value)?;
+        }
+        for (name, value) in self.pred.iter() {
+            params.pred_params.set(name, *value)?;
+        }
+        params.validate()?;
+        Ok(params)
+    }
+}
+
 fn main() {
-    //optimise_deaths_pred();
-    view_model_from_json(test_params_from_json("310",vec![3,2]));
-    // test_plots();
-    // graphics::start_game();
-    // test_avg_vel();
-    // test_num_groups();
-    // test_prey_alive();
-    // test_death_positions();
-    // optimise_regime();
-    // let results = testing::import_result("330");
-    // {
-    //     let params = test_params_from_json("210", vec![4,3]);
-    //     test_death_positions(&params, "_balling");
-    // }
-    // {
-    //     let params = test_params_from_json("110", vec![4,3]);
-    //     test_death_positions(&params, "_sweeping");
-    // }
-    // {
-    //     let params = test_params_from_json("130", vec![1,0]);
-    //     test_death_positions(&params, "_initial");
-    // }
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Run { scenario, output } => scenario.parameters().and_then(|p| run(&p, &output)),
+        Command::View { scenario, checkpoint, result, index } => match (checkpoint, result) {
+            (Some(path), _) => graphics::start_game_from_checkpoint(&path).map_err(|e| e.into()),
+            (None, Some(sim)) => {
+                test_params_from_json(&sim, index)
+                    .and_then(|p| graphics::start_game_from_parameters(&p).map_err(|e| e.into()))
+            }
+            (None, None) => scenario
+                .parameters()
+                .and_then(|p| graphics::start_game_from_parameters(&p).map_err(|e| e.into())),
+        },
+        Command::Replicate { scenario, runs, output } => scenario
+            .parameters()
+            .and_then(|p| replicate(&p, runs, output.as_deref())),
+        Command::Optimise(args) => optimise(&args),
+        Command::Analyse { trajectory, output } => analyse(&trajectory, output.as_deref()),
+        Command::Replay { trajectory } => graphics::start_replay(&trajectory).map_err(|e| e.into()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 
-fn view_model_from_json(params: Parameters) {
-    graphics::start_game_from_parameters(&params);
+fn optimise(args: &OptimiseArgs) -> Result<(), Box<dyn Error>> {
+    let (mut physical_params, mut space_params, foraging) = match &args.scenario {
+        Some(path) => {
+            let params = Parameters::from_file(path)?;
+            (
+                vec![params.pred_params.max_acceleration, params.pred_params.max_vel],
+                vec![params.bound_length, params.num_pred as f32],
+                Foraging::from(&params),
+            )
+        }
+        None => (vec![3.0, 1.25], vec![10.0, 3.0], Foraging::none()),
+    };
+    if let Some(acceleration) = args.pred_max_acceleration {
+        physical_params[0] = acceleration;
+    }
+    if let Some(vel) = args.pred_max_vel {
+        physical_params[1] = vel;
+    }
+    if let Some(length) = args.bound_length {
+        space_params[0] = length;
+    }
+    if let Some(n) = args.num_pred {
+        space_params[1] = n as f32;
+    }
+    let objective = match args.objective {
+        ObjectiveArg::Deaths => Objective::Deaths,
+        ObjectiveArg::Intake => Objective::Intake,
+        ObjectiveArg::DeathsAndIntake => Objective::DeathsAndIntake(args.intake_weight),
+    };
+    if !matches!(objective, Objective::Deaths)
+        && (foraging.resource.is_none() || foraging.intake_rate <= 0.0)
+    {
+        return Err(
+            "intake objectives need --scenario with a resource and a positive intake_rate"
+                .into(),
+        );
+    }
+    co_evolve(&physical_params, &space_params, args.rounds, &foraging, &objective).save(&args.output)?;
+    Ok(())
+}
+
+fn summarise(model: &Model) {
+    println!("Time: {}", model.times.times[model.times.current_index]);
+    println!("Prey alive: {}", prop_alive(model));
+    println!("Kills: {}", model.kills.len());
+    println!("Kill share: {}", kill_share(model));
+    if let Some(density) = mean_kill_density(model) {
+        println!("Mean local density at kills: {}", density);
+    }
+    println!("Prey order: {}", prey_order(model, model.times.current_index));
+    if model.resource.is_some() {
+        println!("Mean intake: {}", mean_intake(model));
+    }
+}
+
+fn run(params: &Parameters, output: &str) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(output)?;
+    params.save(&format!("{}/scenario.toml", output))?;
+    let mut model = Model::from(params);
+    let now = Instant::now();
+    let reason = model.run();
+    println!("Stopped: {:?} after {:.2?}", reason, now.elapsed());
+    summarise(&model);
+    model.save_checkpoint(&format!("{}/trajectory.json", output))?;
+    output_kills_csv(format!("{}/kills.csv", output), &model)?;
+    Ok(())
 }
 
-fn test_params_from_json(sim: &str, index: Vec<usize>) -> Parameters {
-    let result = testing::import_result(sim);
-    let x = match &sim[0..1] {
-        "1" => vec![1.0,0.75],
-        "2" => vec![2.0,1.0],
-        "3" => vec![3.0,1.25],
-        _ => vec![0.0,0.0]
+fn replicate(params: &Parameters, runs: usize, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut dead = Vec::new();
+    let mut kills = Vec::new();
+    let mut order = Vec::new();
+    let mut end = Vec::new();
+    for i in 0..runs {
+        let mut model = Model::from(params);
+        let reason = model.run();
+        println!("Run {}: {:?}", i, reason);
+        dead.push(final_prop_dead(&model));
+        kills.push(model.kills.len() as f32);
+        order.push(prey_order(&model, model.times.current_index));
+        end.push(model.times.times[model.times.current_index]);
+    }
+    for (name, values) in [
+        ("Proportion dead", &dead),
+        ("Kills", &kills),
+        ("Final prey order", &order),
+        ("End time", &end),
+    ] {
+        println!(
+            "{}: mean {}, std {}",
+            name,
+            mean(values).unwrap_or(0.0),
+            std_deviation(values).unwrap_or(0.0)
+        );
+    }
+    if let Some(path) = output {
+        write_to_file(path.to_owned(), vec![dead, kills, order, end])?;
+    }
+    Ok(())
+}
+
+fn analyse(trajectory: &str, output: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let model = Model::load_checkpoint(trajectory)?;
+    summarise(&model);
+    if let Some(dir) = output {
+        std::fs::create_dir_all(dir)?;
+        let steps = model.times.current_index + 1;
+        let order = (0..steps).map(|t| prey_order(&model, t)).collect();
+        write_to_file(
+            format!("{}/order.csv", dir),
+            vec![model.times.times[..steps].to_vec(), order],
+        )?;
+        output_pos_vel(format!("{}/pos_vel.csv", dir), &model);
+        output_kills_csv(format!("{}/kills.csv", dir), &model)?;
+        output_alarm(format!("{}/alarm.csv", dir), &model);
+        output_behaviour(format!("{}/behaviour.csv", dir), &model);
+    }
+    Ok(())
+}
+
+// sim names a results file by its regime and scenario digits, e.g. "31"
+fn test_params_from_json(sim: &str, index: Vec<usize>) -> Result<Parameters, Box<dyn Error>> {
+    let digits: Vec<char> = sim.chars().collect();
+    let (x, y) = match digits[..] {
+        [regime, scenario] => (regime, scenario),
+        _ => return Err(format!("--result must be two digits, got {}", sim).into()),
     };
-    let y = match &sim[1..2] {
-        "1" => vec![10.0,3.0],
-        "2" => vec![20.0,12.0],
-        "3" => vec![30.0,27.0],
-        _ => vec![0.0,0.0]
+    let x = match x {
+        '1' => vec![1.0,0.75],
+        '2' => vec![2.0,1.0],
+        '3' => vec![3.0,1.25],
+        _ => return Err(format!("unknown regime {} in --result {}", x, sim).into()),
     };
-    testing::build_params(&result, x, y, index)
+    let y = match y {
+        '1' => vec![10.0,3.0],
+        '2' => vec![20.0,12.0],
+        '3' => vec![30.0,27.0],
+        _ => return Err(format!("unknown scenario {} in --result {}", y, sim).into()),
+    };
+    let result = testing::import_result(sim)?;
+    if index[0] >= result.pred_behaviour_params.len()
+        || index[1] >= result.prey_behaviour_params.len()
+    {
+        return Err(format!(
+            "--index out of range, results have {} predator and {} prey rounds",
+            result.pred_behaviour_params.len(),
+            result.prey_behaviour_params.len()
+        )
+        .into());
+    }
+    Ok(testing::build_params(&result, x, y, index))
 }
 
 fn test_model() {
@@ -69,45 +361,35 @@ fn test_model() {
     //         0.856870731005716
 
     let prey_params = PreyParams {
-        vision_radius: 1.0,
-        current_direction: 0.0, // not in use
         prey_alignment: 0.5942835072783885, //11.663819253664858,
         prey_attraction:  0.4764408963394583, //-2.598339198694632,
         prey_repulsion: -0.014779979372132102, //8.98344680799392,
         predator_alignment: 0.8065690017890519, //1.6121609117313664,
-        predator_centering: 0.0,
         predator_repulsion: 0.856870731005716, //8.438545382876004,
-        max_acceleration: 2.0,
-        max_vel: 1.0,
-        boundary: 20.0, // not in use
+        ..PreyParams::new()
     };
     // 0.4597890259042512,
     //         0.8898593906962953,
     //         0.05806220760232625,
     //         0.02606796932150995
     let pred_params = PredParams {
-        vision_radius: 2.0,
-        current_direction: 0.0, // not in use
-        prey_alignment: 0.0,
         prey_attraction: 0.4597890259042512, //0.9743652540276795,
-        nearest_prey: 0.0, // not in use
         predator_alignment: 0.8898593906962953, //-0.20327822862488437,
         predator_attraction: 0.05806220760232625, //-0.22612876961363704,
         predator_repulsion: 0.02606796932150995, //-0.4092613999611435,
         max_acceleration: 1.0,
         max_vel: 0.75,
-        boundary: 20.0, //not in use
-        cooldown: 0.5,
+        ..PredParams::new()
     };
     let params = Parameters {
         // Model
         num_prey: 400,
         num_pred: 3,
-        bound_length: 10.0,
         boundary_condition: BC::Soft(2.0), // only current BCmain
         times: Time::new(1.0 / 60.0, 200.0),
         prey_params,
         pred_params,
+        ..Parameters::default()
     };
     let mut model = Model::from(&params);
     //let mut model = Model::new();
@@ -123,31 +405,26 @@ fn test_model() {
 
 fn diagram_generator() {
     let prey_params = PreyParams {
-        vision_radius: 1.0,
-        current_direction: 0.0, // not in use
         prey_alignment: 1.0,
         prey_attraction: 0.5,
         prey_repulsion: 0.1,
         predator_alignment: 5.0,
-        predator_centering: 0.0,
         predator_repulsion: 5.0,
         max_acceleration: 1.0,
-        max_vel: 1.0,
-        boundary: 20.0, // not in use
+        ..PreyParams::new()
     };
     let pred_params = PredParams {
-        vision_radius: 3.0,
-        current_direction: 0.0, // not in use
-        prey_alignment: 0.0,
+        chase_radius: 3.0,
+        align_radius: 3.0,
+        attract_radius: 3.0,
+        repel_radius: 3.0,
         prey_attraction: 5.0,
-        nearest_prey: 0.0, // not in use
         predator_alignment: 1.0,
         predator_attraction: 2.0,
         predator_repulsion: 2.0,
         max_acceleration: 0.5,
         max_vel: 2.0,
-        boundary: 20.0, //not in use
-        cooldown: 0.5,
+        ..PredParams::new()
     };
     let params = Parameters {
         // Model
@@ -158,6 +435,7 @@ fn diagram_generator() {
         times: Time::new(1.0 / 60.0, 50.0),
         prey_params,
         pred_params,
+        ..Parameters::default()
     };
     graphics::start_game_from_parameters(&params);
 }
@@ -165,41 +443,36 @@ fn diagram_generator() {
 fn test_plots() {
     for p in vec![0, 2, 10] {
         let prey_params = PreyParams {
-            vision_radius: 1.0,
-            current_direction: 0.0, // not in use
             prey_alignment: 1.0,
             prey_attraction: 0.30,
             prey_repulsion: 0.1,
             predator_alignment: 5.0,
-            predator_centering: 0.0,
             predator_repulsion: 5.0,
             max_acceleration: 1.0,
-            max_vel: 1.0,
-            boundary: 20.0, // not in use
+            ..PreyParams::new()
         };
         let pred_params = PredParams {
-            vision_radius: 3.0,
-            current_direction: 0.0, // not in use
-            prey_alignment: 0.0,
+            chase_radius: 3.0,
+            align_radius: 3.0,
+            attract_radius: 3.0,
+            repel_radius: 3.0,
             prey_attraction: 5.0,
-            nearest_prey: 0.0, // not in use
             predator_alignment: 1.0,
             predator_attraction: 2.0,
             predator_repulsion: 2.0,
             max_acceleration: 1.0,
             max_vel: 0.7,
-            boundary: 20.0, //not in use
-            cooldown: 0.5,
+            ..PredParams::new()
         };
         let params = Parameters {
             // Model
             num_prey: 750,
             num_pred: p,
-            bound_length: 10.0,
             boundary_condition: BC::Soft(1.5), // only current BC
             times: Time::new(1.0 / 60.0, 50.0),
             prey_params,
             pred_params,
+            ..Parameters::default()
         };
         let mut model = Model::from(&params);
         model.run();
@@ -1,14 +1,35 @@
+use crate::behaviour::Perception;
 use crate::boid::{Agent, AgentType, PredParams, PreyParams, State};
-use crate::graphics::{GUIParameters, CREAM, LRED};
+use crate::field::{Pheromone, Resource};
+use crate::flow::Flow;
+use crate::graphics::{GUIParameters, CREAM, LRED, PHEROMONE, RESOURCE};
 use crate::graphics::{PlayState, BOID_SIZE, DT, WINDOW_HEIGHT, WINDOW_WIDTH};
 use crate::grid::Grid;
+use crate::observer::{Control, Observer};
+use crate::refuge::{self, PredatorAccess, Refuge};
+use crate::scenario::{Event, Schedule, Species};
+use crate::stopping::{StopCondition, StopReason};
+use crate::plot::{prey_order, prop_alive};
+use crate::variation::{sample_traits, Variation};
 use ggez::glam::Vec2;
 use ggez::{graphics, Context};
-use rand::Rng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rand_distr::{Distribution, Normal, NormalError};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
 use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::time::Instant;
 
-#[derive(Debug, Clone)]
+// Relaxation passes per step when pushing overlapping bodies apart
+const COLLISION_ITERATIONS: usize = 4;
+
+// Scenario files usually give only dt and endtime
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Time {
     pub times: Vec<f32>,
     pub dt: f32,
@@ -16,6 +37,12 @@ pub struct Time {
     pub current_index: usize,
 }
 
+impl Default for Time {
+    fn default() -> Time {
+        Time::new(DT, 50.0)
+    }
+}
+
 impl Time {
     pub fn new(dt: f32, endtime: f32) -> Time {
         Time {
@@ -37,13 +64,124 @@ impl Time {
     }
 }
 
-#[derive(Debug, Clone)]
+// Lengths and positions are fractions of the boundary length, except for File which
+// holds absolute positions and velocities (one "x,y,vx,vy" row per agent)
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum IC {
     Random,
-    School,
+    School { centre: Vec2, radius: f32, heading: f32 },
+    Mill { centre: Vec2, radius: f32, width: f32 },
+    Lattice { spacing: f32 },
+    Clusters { num_clusters: usize, std: f32 },
+    Rect { xmin: f32, xmax: f32, ymin: f32, ymax: f32 },
+    File(String),
+}
+
+fn random_heading<R: Rng>(rng: &mut R) -> Vec2 {
+    let angle: f32 = rng.gen_range(0.0..2.0 * PI);
+    Vec2::new(angle.cos(), angle.sin())
 }
 
-#[derive(Debug, Clone)]
+impl IC {
+    pub fn prey_default() -> IC {
+        IC::Rect { xmin: 0.0, xmax: 1.0, ymin: 0.2, ymax: 1.0 }
+    }
+
+    pub fn pred_default() -> IC {
+        IC::Rect { xmin: 0.0, xmax: 1.0, ymin: 0.0, ymax: 0.1 }
+    }
+
+    // The first n "x,y,vx,vy" rows of an initial condition file
+    pub fn read_file(path: &str, n: usize) -> Result<Vec<(Vec2, Vec2)>, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path)
+            .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        let mut states = Vec::new();
+        for (row, record) in reader.records().take(n).enumerate() {
+            let record = record.map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let v: Vec<f32> = record
+                .iter()
+                .map(|x| x.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{} row {}: {}", path, row + 1, e))?;
+            if v.len() < 4 {
+                return Err(format!("{} row {} needs x,y,vx,vy", path, row + 1));
+            }
+            states.push((Vec2::new(v[0], v[1]), Vec2::new(v[2], v[3])));
+        }
+        if states.len() < n {
+            return Err(format!("{} has {} rows but {} agents were requested", path, states.len(), n));
+        }
+        Ok(states)
+    }
+
+    // Positions and velocities for n agents
+    pub fn generate<R: Rng>(&self, n: usize, bound_length: f32, rng: &mut R) -> Vec<(Vec2, Vec2)> {
+        let mut states: Vec<(Vec2, Vec2)> = Vec::new();
+        match self {
+            IC::Random => {
+                for _ in 0..n {
+                    let pos = Vec2::new(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+                    states.push((pos * bound_length, random_heading(rng)));
+                }
+            }
+            IC::School { centre, radius, heading } => {
+                let vel = Vec2::new(heading.cos(), heading.sin());
+                for _ in 0..n {
+                    let r = radius * rng.gen_range(0.0f32..1.0).sqrt();
+                    let offset = r * random_heading(rng);
+                    states.push(((*centre + offset) * bound_length, vel));
+                }
+            }
+            IC::Mill { centre, radius, width } => {
+                for _ in 0..n {
+                    let r = radius + width * rng.gen_range(-0.5..0.5);
+                    let dir = random_heading(rng);
+                    // Anticlockwise tangent to the ring
+                    states.push(((*centre + r * dir) * bound_length, dir.perp()));
+                }
+            }
+            IC::Lattice { spacing } => {
+                let side = (n as f32).sqrt().ceil() as usize;
+                let start = Vec2::splat(0.5 - 0.5 * spacing * (side as f32 - 1.0));
+                for a in 0..n {
+                    let pos = start + *spacing * Vec2::new((a % side) as f32, (a / side) as f32);
+                    states.push((pos * bound_length, random_heading(rng)));
+                }
+            }
+            IC::Clusters { num_clusters, std } => {
+                let centres: Vec<Vec2> = (0..*num_clusters)
+                    .map(|_| Vec2::new(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)))
+                    .collect();
+                let normal = Normal::new(0.0, *std).unwrap();
+                for a in 0..n {
+                    let offset = Vec2::new(normal.sample(rng), normal.sample(rng));
+                    let pos = centres[a % num_clusters] + offset;
+                    states.push((pos * bound_length, random_heading(rng)));
+                }
+            }
+            IC::Rect { xmin, xmax, ymin, ymax } => {
+                for _ in 0..n {
+                    let x: f32 = rng.gen_range(*xmin..*xmax) * bound_length;
+                    let y: f32 = rng.gen_range(*ymin..*ymax) * bound_length;
+                    states.push((Vec2::new(x, y), random_heading(rng)));
+                }
+            }
+            IC::File(path) => {
+                states = IC::read_file(path, n).unwrap_or_else(|e| panic!("{}", e));
+            }
+        }
+        // Keep everyone inside the domain so the grid can place them
+        for (pos, _) in states.iter_mut() {
+            pos.x = pos.x.rem_euclid(bound_length);
+            pos.y = pos.y.rem_euclid(bound_length);
+        }
+        states
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BC {
     Soft(f32),
     Periodic,
@@ -110,6 +248,53 @@ pub fn soft_boundary(pos: &Vec2, bound_length: f32, boundary_range: f32) -> Vec2
     vec
 }
 
+// Species parameters with each varied trait drawn for a single agent
+fn varied_prey<R: Rng>(
+    params: &PreyParams,
+    variation: &Variation,
+    rng: &mut R,
+) -> (AgentType, Vec<(String, f32)>) {
+    let mut params = params.clone();
+    let traits = sample_traits(variation, rng);
+    for (name, value) in traits.iter() {
+        params.set(name, *value).expect("Invalid prey variation");
+    }
+    (AgentType::prey_from_params(params), traits)
+}
+
+fn varied_pred<R: Rng>(
+    params: &PredParams,
+    variation: &Variation,
+    rng: &mut R,
+) -> (AgentType, Vec<(String, f32)>) {
+    let mut params = params.clone();
+    let traits = sample_traits(variation, rng);
+    for (name, value) in traits.iter() {
+        params.set(name, *value).expect("Invalid predator variation");
+    }
+    (AgentType::pred_from_params(params), traits)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillEvent {
+    pub time: f32,
+    pub time_index: usize,
+    pub prey_id: usize,
+    pub predator_id: usize,
+    pub prey_position: Vec2,
+    pub prey_velocity: Vec2,
+    pub predator_position: Vec2,
+    pub predator_velocity: Vec2,
+    // Other living prey within the victim's largest perception radius
+    pub local_density: usize,
+    // Distance from the victim to the centroid of all living prey
+    pub centroid_distance: f32,
+}
+
+// Loaded from and saved to scenario files in config.rs, omitted fields take the
+// values from Parameters::default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Parameters {
     // Model
     pub num_prey: usize,
@@ -119,19 +304,91 @@ pub struct Parameters {
     pub times: Time,
     pub prey_params: PreyParams,
     pub pred_params: PredParams,
+    pub prey_ic: IC,
+    pub pred_ic: IC,
+    // Parameters drawn per agent at creation, overriding prey_params / pred_params
+    pub prey_variation: Variation,
+    pub pred_variation: Variation,
+    // Resolve overlaps between agents with a non-zero body_radius after each step
+    pub collisions: bool,
+    pub flow: Flow,
+    // Regions where prey cannot be captured
+    pub refuges: Vec<Refuge>,
+    // Food grazed by prey with a non-zero intake_rate
+    pub resource: Option<Resource>,
+    // Trail field shared by both species
+    pub pheromone: Option<Pheromone>,
+    // Checked after every step of Model::run
+    pub stop_conditions: Vec<StopCondition>,
+    pub schedule: Schedule,
+    // Off for long streaming runs, see Model::drop_history
+    pub keep_history: bool,
+}
+
+impl Default for Parameters {
+    fn default() -> Parameters {
+        Parameters {
+            num_prey: 200,
+            num_pred: 10,
+            bound_length: 10.0,
+            boundary_condition: BC::Soft(0.5),
+            times: Time::default(),
+            prey_params: PreyParams::new(),
+            pred_params: PredParams::new(),
+            prey_ic: IC::prey_default(),
+            pred_ic: IC::pred_default(),
+            prey_variation: Vec::new(),
+            pred_variation: Vec::new(),
+            collisions: false,
+            flow: Flow::still(),
+            refuges: Vec::new(),
+            resource: None,
+            pheromone: None,
+            stop_conditions: Vec::new(),
+            schedule: Schedule::new(),
+            keep_history: true,
+        }
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Model {
     pub num_prey: usize,
-    num_pred: usize,
+    pub num_pred: usize,
     pub times: Time,
     pub agents: Vec<Agent>,
     pub vision_radius: f32,
     bound_length: f32,
     scale: f32,
-    vision_ratio: usize,
     pub boundary_condition: BC,
     pub grid: Grid,
+    pub prey_params: PreyParams,
+    pub pred_params: PredParams,
+    pub prey_variation: Variation,
+    pub pred_variation: Variation,
+    // Where scheduled spawns place new agents
+    pub prey_ic: IC,
+    pub pred_ic: IC,
+    pub schedule: Schedule,
+    pub collisions: bool,
+    pub flow: Flow,
+    pub refuges: Vec<Refuge>,
+    pub resource: Option<Resource>,
+    pub pheromone: Option<Pheromone>,
+    pub stop_conditions: Vec<StopCondition>,
+    // Order parameter and the time it was recorded, for StopCondition::OrderStable
+    order_reference: Option<(f32, f32)>,
+    // Every capture in the order it happened
+    pub kills: Vec<KillEvent>,
+    #[serde(skip)]
+    observers: Vec<Box<dyn Observer>>,
+    notified_kills: usize,
+    pub keep_history: bool,
+    // Steps forgotten by drop_history, add to a time index to count from the start of the run
+    pub dropped_steps: usize,
+    next_id: usize,
+    // All randomness after construction comes from here so a checkpoint resumes exactly
+    rng: ChaCha8Rng,
 }
 
 impl Model {
@@ -145,30 +402,20 @@ impl Model {
         // Create agents
         let mut grid = Grid::new(vision_radius, bound_length);
         for a in 0..num_prey {
-            let agent = Agent::new(bound_length, AgentType::new_prey());
+            let mut agent = Agent::new(bound_length, AgentType::new_prey());
+            agent.id = a;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
         let num_pred = 10;
         for a in num_prey..num_prey + num_pred {
-            let agent = Agent::new(bound_length, AgentType::new_predator());
+            let mut agent = Agent::new(bound_length, AgentType::new_predator());
+            agent.id = a;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
-        let mut vision_ratio: usize = 1;
-        if num_pred > 0 {
-            if let AgentType::Predator(_, pred_params) = &agents[num_prey].agent_type {
-                if let AgentType::Prey(_, prey_params) = &agents[0].agent_type {
-                    vision_ratio =
-                        (pred_params.vision_radius / prey_params.vision_radius).ceil() as usize;
-                }
-            }
-        } else {
-            vision_ratio = 1;
-        }
-
         // REMOVE
         //let predator = Agent::new(bound_length, AgentType::new_predator());
         //grid.push_agent(predator);
@@ -181,8 +428,28 @@ impl Model {
             times: Time::new(DT, 50.0),
             bound_length,
             scale: WINDOW_WIDTH / bound_length,
-            vision_ratio,
             boundary_condition: BC::Soft(0.5),
+            prey_params: PreyParams::new(),
+            pred_params: PredParams::new(),
+            prey_variation: Vec::new(),
+            pred_variation: Vec::new(),
+            prey_ic: IC::prey_default(),
+            pred_ic: IC::pred_default(),
+            schedule: Schedule::new(),
+            collisions: false,
+            flow: Flow::still(),
+            refuges: Vec::new(),
+            resource: None,
+            pheromone: None,
+            stop_conditions: Vec::new(),
+            order_reference: None,
+            kills: Vec::new(),
+            observers: Vec::new(),
+            notified_kills: 0,
+            keep_history: true,
+            dropped_steps: 0,
+            next_id: num_prey + num_pred,
+            rng: ChaCha8Rng::from_entropy(),
         }
     }
 
@@ -190,34 +457,63 @@ impl Model {
         let num_prey = parameters.num_prey;
         let num_pred = parameters.num_pred;
         let bound_length = parameters.bound_length;
-        let vision_radius = parameters.prey_params.vision_radius;
+        let vision_radius = parameters.prey_params.max_radius();
         let times = parameters.times.clone();
         let boundary_condition = parameters.boundary_condition.clone();
+        let mut schedule = parameters.schedule.clone();
+        schedule.sort();
         let mut agents = Vec::new();
+        let mut rng = ChaCha8Rng::from_entropy();
+
+        // Bad override names would otherwise only show up when an agent enters that state
+        if let Some(fsm) = &parameters.prey_params.behaviour {
+            let mut params = parameters.prey_params.clone();
+            fsm.check_overrides(|name, value| params.set(name, value))
+                .expect("Invalid prey behaviour override");
+        }
+        if let Some(fsm) = &parameters.pred_params.behaviour {
+            let mut params = parameters.pred_params.clone();
+            fsm.check_overrides(|name, value| params.set(name, value))
+                .expect("Invalid predator behaviour override");
+        }
+        let mut flow = parameters.flow.clone();
+        flow.field = flow.field.load().expect("Invalid flow field file");
 
         // Create agents
         let mut grid = Grid::new(vision_radius, bound_length);
+        let prey_states = parameters.prey_ic.generate(num_prey, bound_length, &mut rng);
         for a in 0..num_prey {
-            let agent = Agent::new(
-                bound_length,
-                AgentType::prey_from_params(parameters.prey_params.clone()),
-            );
+            let (agent_type, traits) =
+                varied_prey(&parameters.prey_params, &parameters.prey_variation, &mut rng);
+            let mut agent = Agent::with_state(agent_type, prey_states[a].0, prey_states[a].1);
+            agent.id = a;
+            agent.traits = traits;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
+        // Informed prey are chosen at random
+        let num_informed =
+            (parameters.prey_params.informed_fraction * num_prey as f32).round() as usize;
+        for a in rand::seq::index::sample(&mut rng, num_prey, num_informed.min(num_prey)) {
+            agents[a].informed = true;
+        }
+
+        let pred_states = parameters.pred_ic.generate(num_pred, bound_length, &mut rng);
         for a in num_prey..num_prey + num_pred {
-            let agent = Agent::new(
-                bound_length,
-                AgentType::pred_from_params(parameters.pred_params.clone()),
+            let (agent_type, traits) =
+                varied_pred(&parameters.pred_params, &parameters.pred_variation, &mut rng);
+            let mut agent = Agent::with_state(
+                agent_type,
+                pred_states[a - num_prey].0,
+                pred_states[a - num_prey].1,
             );
+            agent.id = a;
+            agent.traits = traits;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
-        let vision_ratio: usize = (parameters.pred_params.vision_radius
-            / parameters.prey_params.vision_radius)
-            .ceil() as usize;
         Model {
             num_prey,
             num_pred,
@@ -227,57 +523,58 @@ impl Model {
             times,
             bound_length,
             scale: WINDOW_WIDTH / bound_length,
-            vision_ratio,
             boundary_condition,
+            prey_params: parameters.prey_params.clone(),
+            pred_params: parameters.pred_params.clone(),
+            prey_variation: parameters.prey_variation.clone(),
+            pred_variation: parameters.pred_variation.clone(),
+            prey_ic: parameters.prey_ic.clone(),
+            pred_ic: parameters.pred_ic.clone(),
+            schedule,
+            collisions: parameters.collisions,
+            flow,
+            refuges: parameters.refuges.clone(),
+            resource: parameters.resource.clone(),
+            pheromone: parameters.pheromone.clone(),
+            stop_conditions: parameters.stop_conditions.clone(),
+            order_reference: None,
+            kills: Vec::new(),
+            observers: Vec::new(),
+            notified_kills: 0,
+            keep_history: parameters.keep_history,
+            dropped_steps: 0,
+            next_id: num_prey + num_pred,
+            rng,
         }
     }
 
     pub fn graphical_from(ctx: &mut Context, parameters: &Parameters) -> Model {
-        let num_prey = parameters.num_prey;
-        let num_pred = parameters.num_pred;
-        let bound_length = parameters.bound_length;
-        let vision_radius = parameters.prey_params.vision_radius;
-        let times = parameters.times.clone();
-        let boundary_condition = parameters.boundary_condition.clone();
-        let mut agents = Vec::new();
-
-        // Create agents
-        let mut grid = Grid::new(vision_radius, bound_length);
-        for a in 0..num_prey {
-            let agent = Agent::new_graphical(
-                ctx,
-                bound_length,
-                AgentType::prey_from_params(parameters.prey_params.clone()),
-            );
-            grid.push_agent(&agent.positions[0], a);
-            agents.push(agent);
+        let mut model = Model::from(parameters);
+        for agent in model.agents.iter_mut() {
+            agent.ensure_polygon(ctx, model.scale);
         }
+        model
+    }
 
-        for a in num_prey..num_prey + num_pred {
-            let agent = Agent::new_graphical(
-                ctx,
-                bound_length,
-                AgentType::pred_from_params(parameters.pred_params.clone()),
-            );
-            grid.push_agent(&agent.positions[0], a);
-            agents.push(agent);
-        }
+    // Everything needed to continue the run exactly, including the random number
+    // generator. Observers are not saved and must be added again after loading.
+    pub fn save_checkpoint(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
 
-        let vision_ratio: usize = (parameters.pred_params.vision_radius
-            / parameters.prey_params.vision_radius)
-            .ceil() as usize;
-        Model {
-            num_prey,
-            num_pred,
-            agents,
-            vision_radius,
-            grid,
-            times,
-            bound_length,
-            scale: WINDOW_WIDTH / bound_length,
-            vision_ratio,
-            boundary_condition,
+    pub fn load_checkpoint(path: &str) -> Result<Model, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn graphical_from_checkpoint(ctx: &mut Context, path: &str) -> Result<Model, Box<dyn Error>> {
+        let mut model = Model::load_checkpoint(path)?;
+        for agent in model.agents.iter_mut() {
+            agent.ensure_polygon(ctx, model.scale);
         }
+        Ok(model)
     }
 
     pub fn new_graphical(ctx: &mut Context) -> Model {
@@ -290,41 +587,51 @@ impl Model {
         // Create grid and assign agents
         let mut grid = Grid::new(vision_radius, bound_length);
         for a in 0..num_prey {
-            let agent = Agent::new_graphical(ctx, bound_length, AgentType::new_prey());
+            let mut agent = Agent::new_graphical(ctx, bound_length, AgentType::new_prey());
+            agent.id = a;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
         let num_pred = 0;
         for a in num_prey..num_prey + num_pred {
-            let agent = Agent::new_graphical(ctx, bound_length, AgentType::new_predator());
+            let mut agent = Agent::new_graphical(ctx, bound_length, AgentType::new_predator());
+            agent.id = a;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
-        let mut vision_ratio: usize = 1;
-        if num_pred > 0 {
-            if let AgentType::Predator(_, pred_params) = &agents[num_prey].agent_type {
-                if let AgentType::Prey(_, prey_params) = &agents[0].agent_type {
-                    vision_ratio =
-                        (pred_params.vision_radius / prey_params.vision_radius).ceil() as usize;
-                }
-            }
-        } else {
-            vision_ratio = 1;
-        }
-
         Model {
             num_prey,
             num_pred,
             agents,
-            vision_ratio,
             grid,
             times: Time::new(DT, 50.0),
             bound_length,
             scale: WINDOW_WIDTH / bound_length,
             vision_radius,
             boundary_condition: BC::Soft(5.0),
+            prey_params: PreyParams::new(),
+            pred_params: PredParams::new(),
+            prey_variation: Vec::new(),
+            pred_variation: Vec::new(),
+            prey_ic: IC::prey_default(),
+            pred_ic: IC::pred_default(),
+            schedule: Schedule::new(),
+            collisions: false,
+            flow: Flow::still(),
+            refuges: Vec::new(),
+            resource: None,
+            pheromone: None,
+            stop_conditions: Vec::new(),
+            order_reference: None,
+            kills: Vec::new(),
+            observers: Vec::new(),
+            notified_kills: 0,
+            keep_history: true,
+            dropped_steps: 0,
+            next_id: num_prey + num_pred,
+            rng: ChaCha8Rng::from_entropy(),
         }
     }
 
@@ -371,36 +678,26 @@ impl Model {
         // Create grid and assign agents
         let mut grid = Grid::new(vision_radius, bound_length);
         for a in 0..num_prey {
-            let agent = Agent::new_graphical(
+            let mut agent = Agent::new_graphical(
                 ctx,
                 bound_length,
                 AgentType::prey_from_params(PreyParams::from_params(&mut parameters.prey_params)),
             );
+            agent.id = a;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
         for a in num_prey..num_prey + num_pred {
-            let agent = Agent::new_graphical(
+            let mut agent = Agent::new_graphical(
                 ctx,
                 bound_length,
                 AgentType::pred_from_params(PredParams::from_params(&mut parameters.pred_params)),
             );
+            agent.id = a;
             grid.push_agent(&agent.positions[0], a);
             agents.push(agent);
         }
 
-        let mut vision_ratio: usize = 1;
-        if num_pred > 0 {
-            if let AgentType::Predator(_, pred_params) = &agents[num_prey].agent_type {
-                if let AgentType::Prey(_, prey_params) = &agents[0].agent_type {
-                    vision_ratio =
-                        (pred_params.vision_radius / prey_params.vision_radius).ceil() as usize;
-                }
-            }
-        } else {
-            vision_ratio = 1;
-        }
-
         Model {
             num_prey,
             num_pred,
@@ -410,166 +707,439 @@ impl Model {
             bound_length,
             scale: WINDOW_WIDTH / bound_length,
             vision_radius,
-            vision_ratio,
             boundary_condition: BC::Periodic,
+            prey_params: PreyParams::from_params(&mut parameters.prey_params),
+            pred_params: PredParams::from_params(&mut parameters.pred_params),
+            prey_variation: Vec::new(),
+            pred_variation: Vec::new(),
+            prey_ic: IC::prey_default(),
+            pred_ic: IC::pred_default(),
+            schedule: Schedule::new(),
+            collisions: false,
+            flow: Flow::still(),
+            refuges: Vec::new(),
+            resource: None,
+            pheromone: None,
+            stop_conditions: Vec::new(),
+            order_reference: None,
+            kills: Vec::new(),
+            observers: Vec::new(),
+            notified_kills: 0,
+            keep_history: true,
+            dropped_steps: 0,
+            next_id: num_prey + num_pred,
+            rng: ChaCha8Rng::from_entropy(),
         }
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> StopReason {
+        let started = Instant::now();
+        let mut reason = StopReason::EndTime;
         while self.times.times[self.times.current_index] < self.times.endtime {
-            self.step();
+            if self.step() == Control::Stop {
+                reason = StopReason::Observer;
+                break;
+            }
+            if let Some(r) = self.check_stop(started) {
+                reason = r;
+                break;
+            }
+        }
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in observers.iter_mut() {
+            observer.on_run_end(self);
+        }
+        self.observers = observers;
+        reason
+    }
+
+    fn check_stop(&mut self, started: Instant) -> Option<StopReason> {
+        let t = self.times.current_index;
+        let time = self.times.times[t];
+        for i in 0..self.stop_conditions.len() {
+            match &self.stop_conditions[i] {
+                StopCondition::AllPreyDead => {
+                    if prop_alive(self) <= 0.0 {
+                        return Some(StopReason::AllPreyDead);
+                    }
+                }
+                StopCondition::PreyFraction(fraction) => {
+                    if prop_alive(self) <= *fraction {
+                        return Some(StopReason::PreyFraction);
+                    }
+                }
+                StopCondition::OrderStable { tolerance, duration } => {
+                    let order = prey_order(self, t);
+                    match self.order_reference {
+                        Some((reference, since)) if (order - reference).abs() <= *tolerance => {
+                            if time - since >= *duration {
+                                return Some(StopReason::OrderStable);
+                            }
+                        }
+                        _ => self.order_reference = Some((order, time)),
+                    }
+                }
+                StopCondition::WallClock(limit) => {
+                    if started.elapsed() >= *limit {
+                        return Some(StopReason::WallClock);
+                    }
+                }
+                StopCondition::Custom(predicate) => {
+                    if predicate(self) {
+                        return Some(StopReason::Custom);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    pub fn take_observers(&mut self) -> Vec<Box<dyn Observer>> {
+        std::mem::take(&mut self.observers)
+    }
+
+    // Report new kills then the finished step, observers are moved out while they run
+    fn notify_step(&mut self) -> Control {
+        if self.observers.is_empty() {
+            self.notified_kills = self.kills.len();
+            return Control::Continue;
+        }
+        let mut observers = std::mem::take(&mut self.observers);
+        let mut control = Control::Continue;
+        for kill in self.kills[self.notified_kills..].iter() {
+            for observer in observers.iter_mut() {
+                if observer.on_kill(self, kill) == Control::Stop {
+                    control = Control::Stop;
+                }
+            }
+        }
+        self.notified_kills = self.kills.len();
+        for observer in observers.iter_mut() {
+            if observer.on_step(self) == Control::Stop {
+                control = Control::Stop;
+            }
+        }
+        self.observers = observers;
+        control
+    }
+
+    fn apply_events(&mut self) {
+        let events = self.schedule.due(self.times.times[self.times.current_index]);
+        for event in events {
+            match event {
+                Event::Spawn(species, n) => {
+                    for _ in 0..n {
+                        self.spawn(species);
+                    }
+                }
+                Event::Remove(species, n) => self.remove(species, n),
+                Event::SetParam(species, name, value) => self.set_param(species, &name, value),
+                Event::SetBC(bc) => self.boundary_condition = bc,
+            }
+        }
+    }
+
+    fn spawn(&mut self, species: Species) {
+        let (agent_type, traits, ic) = match species {
+            Species::Prey => {
+                let (agent_type, traits) =
+                    varied_prey(&self.prey_params, &self.prey_variation, &mut self.rng);
+                (agent_type, traits, self.prey_ic.clone())
+            }
+            Species::Predator => {
+                let (agent_type, traits) =
+                    varied_pred(&self.pred_params, &self.pred_variation, &mut self.rng);
+                (agent_type, traits, self.pred_ic.clone())
+            }
+        };
+        let (position, velocity) = ic.generate(1, self.bound_length, &mut self.rng)[0];
+        let mut agent = Agent::with_state(agent_type, position, velocity);
+        agent.traits = traits;
+        if species == Species::Prey {
+            let p = self.prey_params.informed_fraction.clamp(0.0, 1.0);
+            agent.informed = self.rng.gen_bool(p as f64);
+        }
+        self.add_agent(agent);
+    }
+
+    fn remove(&mut self, species: Species, n: usize) {
+        let candidates: Vec<usize> = self
+            .agents
+            .iter()
+            .filter(|a| match (&a.agent_type, &a.dead) {
+                (AgentType::Prey(..), State::Alive) => species == Species::Prey,
+                (AgentType::Predator(..), State::Alive) => species == Species::Predator,
+                _ => false,
+            })
+            .map(|a| a.id)
+            .collect();
+        let chosen: Vec<usize> = candidates
+            .choose_multiple(&mut self.rng, n)
+            .copied()
+            .collect();
+        for id in chosen {
+            self.remove_agent(id);
+        }
+    }
+
+    // Add an agent at the current time step and return its id
+    pub fn spawn_agent(&mut self, agent_type: AgentType, position: Vec2, velocity: Vec2) -> usize {
+        self.add_agent(Agent::with_state(agent_type, position, velocity))
+    }
+
+    fn add_agent(&mut self, mut agent: Agent) -> usize {
+        match agent.agent_type {
+            AgentType::Prey(..) => self.num_prey += 1,
+            AgentType::Predator(..) => self.num_pred += 1,
+        }
+        agent.id = self.next_id;
+        self.next_id += 1;
+        agent.pad_history(self.times.current_index);
+        self.grid
+            .push_agent(&agent.positions[self.times.current_index], self.agents.len());
+        self.agents.push(agent);
+        self.next_id - 1
+    }
+
+    // Remove an agent, returning it with its full history
+    pub fn remove_agent(&mut self, id: usize) -> Option<Agent> {
+        let index = self.agent_index(id)?;
+        self.grid.remove_agent(index);
+        let agent = self.agents.remove(index);
+        match agent.agent_type {
+            AgentType::Prey(..) => self.num_prey -= 1,
+            AgentType::Predator(..) => self.num_pred -= 1,
+        }
+        Some(agent)
+    }
+
+    pub fn bound_length(&self) -> f32 {
+        self.bound_length
+    }
+
+    pub fn agent_index(&self, id: usize) -> Option<usize> {
+        self.agents.iter().position(|a| a.id == id)
+    }
+
+    pub fn agent(&self, id: usize) -> Option<&Agent> {
+        self.agents.iter().find(|a| a.id == id)
+    }
+
+    fn set_param(&mut self, species: Species, name: &str, value: f32) {
+        let result = match species {
+            Species::Prey => self.prey_params.set(name, value),
+            Species::Predator => self.pred_params.set(name, value),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            return;
+        }
+        for a in self.agents.iter_mut() {
+            match &mut a.agent_type {
+                AgentType::Prey(_, params) if species == Species::Prey => {
+                    params.set(name, value).unwrap();
+                }
+                AgentType::Predator(_, params) if species == Species::Predator => {
+                    params.set(name, value).unwrap();
+                }
+                _ => (),
+            }
         }
     }
 
-    pub fn step(&mut self) {
+    // Advance one time step and notify observers, returning Control::Stop if any of them
+    // asked the run to end
+    pub fn step(&mut self) -> Control {
+        self.advance();
+        let control = self.notify_step();
+        if !self.keep_history {
+            self.drop_history();
+        }
+        control
+    }
+
+    // Forget every step except those reaction delays still look back to. Stored time
+    // indices are shifted down so current_index stays valid, but anything computed over
+    // the whole history (plot.rs outputs, intake totals) only sees what is left, so
+    // streaming runs should record what they need through an Observer.
+    fn drop_history(&mut self) {
+        let delay = self
+            .agents
+            .iter()
+            .map(|a| match &a.agent_type {
+                AgentType::Prey(_, params) => params.reaction_delay,
+                AgentType::Predator(_, params) => params.reaction_delay,
+            })
+            .max()
+            .unwrap_or(0);
+        let n = self.times.current_index.saturating_sub(delay);
+        if n == 0 {
+            return;
+        }
+        self.times.times.drain(..n);
+        self.times.current_index -= n;
+        self.dropped_steps += n;
+        for agent in self.agents.iter_mut() {
+            agent.drop_history(n);
+        }
+    }
+
+    fn advance(&mut self) {
+        self.apply_events();
+        // Copied out while agents borrow the model and written back before it is used again
+        let mut rng = self.rng.clone();
         for c_i in 0..self.grid.num_cells {
             for c_j in 0..self.grid.num_cells {
                 for a_1_i in 0..self.grid.cells[c_i][c_j].agent_indices.len() {
                     let a_1_index = self.grid.cells[c_i][c_j].agent_indices[a_1_i];
-                    let mut bound_force = Vec2::ZERO;
-                    // Soft boundary velocity
-                    match self.boundary_condition {
-                        BC::Soft(br) => {
-                            //if c_i == 0 || c_j == 0 || c_i == self.grid.num_cells-1 || c_j == self.grid.num_cells-1{
-                            // Change to only consider those in grids near the boundary
-                            bound_force = soft_boundary(
-                                &self.agents[a_1_index].positions[self.times.current_index],
-                                self.bound_length,
-                                br,
-                            );
-                        }
-                        _ => (),
-                    }
-                    let F_j = match &self.agents[a_1_index].agent_type {
+                    let mut alarm_level = 0.0;
+                    let mut nearest_predator = f32::INFINITY;
+                    let mut nearest_prey = f32::INFINITY;
+                    let mut hunt = self.agents[a_1_index].hunt.clone();
+                    let agent_type = self.agents[a_1_index].effective_type();
+                    let F_j = match agent_type.as_ref() {
                         AgentType::Prey(_, params) => {
+                            let mut bound_force =
+                                self.wall_force(a_1_index, params.boundary, params.boundary_range);
                             let mut align_vel = Vec2::ZERO;
                             let mut attraction = Vec2::ZERO;
                             let mut prey_repulsion = Vec2::ZERO;
                             let mut pred_centering = Vec2::ZERO;
                             let mut pred_align_vel = Vec2::ZERO;
                             let mut pred_repulsion = Vec2::ZERO;
-                            let mut num_nearby: i32 = 0;
+                            let mut num_align: i32 = 0;
+                            let mut num_attract: i32 = 0;
+                            let mut num_repel: i32 = 0;
                             let mut pred_num_nearby: i32 = 0;
-                            for n in 0..3 {
-                                for m in 0..3 {
-                                    let index_i =
-                                        (c_i as i32 + n as i32 + self.grid.num_cells as i32
-                                            - 1 as i32)
-                                            % self.grid.num_cells as i32;
-                                    let index_j =
-                                        (c_j as i32 + m as i32 + self.grid.num_cells as i32
-                                            - 1 as i32)
-                                            % self.grid.num_cells as i32;
-                                    for a_2_i in 0..self.grid.cells[index_i as usize]
-                                        [index_j as usize]
-                                        .agent_indices
-                                        .len()
-                                    {
-                                        let a_2_index = self.grid.cells[index_i as usize]
-                                            [index_j as usize]
-                                            .agent_indices[a_2_i];
-
-                                        let dist: f32 = distance(
-                                            &self.agents[a_1_index].positions
-                                                [self.times.current_index],
-                                            &self.agents[a_2_index].positions
-                                                [self.times.current_index],
-                                            self.bound_length,
-                                            &self.boundary_condition,
-                                        );
+                            let mut neighbour_alarm: f32 = 0.0;
+                            for (index_i, index_j) in
+                                self.grid.neighbour_cells(c_i, c_j, params.max_radius())
+                            {
+                                for a_2_i in 0..self.grid.cells[index_i][index_j].agent_indices.len() {
+                                    let a_2_index =
+                                        self.grid.cells[index_i][index_j].agent_indices[a_2_i];
+                                    let (seen_pos, seen_vel) = self.perceive(
+                                        a_2_index,
+                                        params.position_noise,
+                                        params.velocity_noise,
+                                        params.reaction_delay,
+                                        &mut rng,
+                                    );
+                                    let dist_vec = distance_vec(
+                                        &self.agents[a_1_index].positions
+                                            [self.times.current_index],
+                                        &seen_pos,
+                                        self.bound_length,
+                                        &self.boundary_condition,
+                                    );
+                                    let dist = dist_vec.length();
 
-                                        // Only align if prey
-                                        // Don't count yourself
-                                        if dist > 0.0000001 {
-                                            match &self.agents[a_2_index].agent_type {
-                                                AgentType::Prey(..) => {
-                                                    if dist < params.vision_radius {
-                                                        align_vel += self.agents[a_2_index]
-                                                            .velocities[self.times.current_index]
+                                    // Don't count yourself
+                                    if a_2_index != a_1_index && dist > 0.0000001 {
+                                        match &self.agents[a_2_index].agent_type {
+                                            AgentType::Prey(..) => {
+                                                nearest_prey = nearest_prey.min(dist);
+                                                if dist < params.align_radius {
+                                                    align_vel += params.align_kernel.weight(dist)
+                                                        * (seen_vel
                                                             - self.agents[a_1_index].velocities
-                                                                [self.times.current_index];
-                                                        let curr_repulsion = distance_vec(
-                                                            self.agents[a_1_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.agents[a_2_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.bound_length,
-                                                            &self.boundary_condition,
-                                                        );
-                                                        prey_repulsion += curr_repulsion
-                                                            / curr_repulsion.length_squared();
-                                                        attraction += distance_vec(
-                                                            self.agents[a_1_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.agents[a_2_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.bound_length,
-                                                            &self.boundary_condition,
-                                                        );
-                                                        num_nearby += 1;
-                                                    }
+                                                                [self.times.current_index]);
+                                                    num_align += 1;
                                                 }
-                                                AgentType::Predator(..) => {
-                                                    if dist < params.vision_radius {
-                                                        pred_align_vel += self.agents[a_2_index].velocities[self.times.current_index];
-                                                        let curr_repulsion = distance_vec(
-                                                            self.agents[a_1_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.agents[a_2_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.bound_length,
-                                                            &self.boundary_condition,
-                                                        );
-                                                        pred_centering += curr_repulsion;
-                                                        pred_repulsion += curr_repulsion
-                                                            / curr_repulsion.length_squared();
-                                                        pred_num_nearby += 1;
+                                                if dist < params.attract_radius {
+                                                    attraction += params.attract_kernel.weight(dist)
+                                                        * dist_vec
+                                                        / dist;
+                                                    num_attract += 1;
+                                                }
+                                                if dist < params.repel_radius {
+                                                    prey_repulsion += params.repel_kernel.weight(dist)
+                                                        * dist_vec
+                                                        / dist;
+                                                    num_repel += 1;
+                                                }
+                                                if let Some(alarm) = &params.alarm {
+                                                    let level = self.agents[a_2_index].alarm
+                                                        [self.times.current_index];
+                                                    if dist < alarm.radius && level >= alarm.threshold {
+                                                        neighbour_alarm = neighbour_alarm.max(level);
                                                     }
                                                 }
                                             }
+                                            AgentType::Predator(..) => {
+                                                nearest_predator = nearest_predator.min(dist);
+                                                if dist < params.threat_radius {
+                                                    pred_align_vel += seen_vel;
+                                                    pred_centering += dist_vec;
+                                                    pred_repulsion += params.threat_kernel.weight(dist)
+                                                        * dist_vec
+                                                        / dist;
+                                                    pred_num_nearby += 1;
+                                                }
+                                            }
                                         }
                                     }
                                 }
                             }
 
-                            if num_nearby > 0 {
-                                align_vel = align_vel / num_nearby as f32;
-                                attraction = attraction / num_nearby as f32;
-                                prey_repulsion = prey_repulsion / (num_nearby) as f32;
-                            }
+                            align_vel = params.normalisation.apply(align_vel, num_align);
+                            attraction = params.normalisation.apply(attraction, num_attract);
+                            prey_repulsion = params.normalisation.apply(prey_repulsion, num_repel);
 
                             if pred_num_nearby > 0 {
                                 pred_align_vel = pred_align_vel / pred_num_nearby as f32;
                                 pred_centering = -1.0 *pred_centering / pred_num_nearby as f32;
-                                pred_repulsion = pred_repulsion / pred_num_nearby as f32;
                             }
+                            pred_repulsion = params.normalisation.apply(pred_repulsion, pred_num_nearby);
                             let mut pre_perp_vel = pred_align_vel.perp();
                             if pred_centering.dot(pre_perp_vel) <= 0.0 {
                                 pre_perp_vel = -1.0*pre_perp_vel;
 
                             }
+                            // Seeing a predator fully alarms a prey, alarmed neighbours pass on
+                            // part of their alarm, and the level otherwise decays
+                            let mut repulsion_gain = 1.0;
+                            if let Some(alarm) = &params.alarm {
+                                let current = self.agents[a_1_index].alarm[self.times.current_index];
+                                let trigger = if pred_num_nearby > 0 {
+                                    1.0
+                                } else {
+                                    alarm.transmission * neighbour_alarm
+                                };
+                                alarm_level = (current * (-alarm.decay * self.times.dt).exp())
+                                    .max(trigger)
+                                    .min(1.0);
+                                repulsion_gain += alarm.repulsion_gain * alarm_level;
+                            }
+                            let fear = if pred_num_nearby > 0 { 1.0 } else { alarm_level };
+                            let refuge_force = params.refuge_attraction
+                                * (1.0 + params.refuge_fear_gain * fear)
+                                * self.refuge_direction(a_1_index);
                             let mut F_j = params.prey_alignment * align_vel
-                                - params.predator_repulsion * pred_repulsion
+                                - repulsion_gain * params.predator_repulsion * pred_repulsion
                                 - params.prey_repulsion * prey_repulsion
                                 + params.prey_attraction * attraction
-                                + params.predator_alignment * pre_perp_vel;
+                                + params.predator_alignment * pre_perp_vel
+                                + params.current_direction * self.preferred_heading(a_1_index)
+                                + refuge_force
+                                + params.forage_weight * self.resource_direction(a_1_index)
+                                + params.pheromone_response * self.pheromone_direction(a_1_index);
+                            if let (true, Some(goal)) = (self.agents[a_1_index].informed, &params.goal) {
+                                F_j += params.goal_weight
+                                    * goal.direction(
+                                        &self.agents[a_1_index].positions[self.times.current_index],
+                                        self.bound_length,
+                                        &self.boundary_condition,
+                                    );
+                            }
                             let F_length = F_j.length();
                             bound_force = bound_force * F_length.max(params.max_acceleration);
                             F_j += bound_force;
-                            let mut rng = rand::thread_rng();
-                            let normal = Normal::new(0.0, 0.05).unwrap();
+                            let normal = Normal::new(0.0, params.noise).unwrap();
                             F_j.x += normal.sample(&mut rng);
                             F_j.y += normal.sample(&mut rng);
                             let F_length = F_j.length();
@@ -582,122 +1152,228 @@ impl Model {
                             F_j
                         }
                         AgentType::Predator(_, params) => {
-                            let mut chase_vel = Vec2::ZERO;
+                            let mut bound_force =
+                                self.wall_force(a_1_index, params.boundary, params.boundary_range);
                             let mut prey_attraction = Vec2::ZERO;
                             let mut pred_repulsion = Vec2::ZERO;
                             let mut pred_alignment = Vec2::ZERO;
                             let mut num_nearby = 0;
-                            let mut pred_num_nearby = 0;
-                            let mut min_dist = self.bound_length;
-
-                            for n in 0..self.vision_ratio + 2 {
-                                for m in 0..self.vision_ratio + 2 {
-                                    let index_i =
-                                        (c_i as i32 + n as i32 + self.grid.num_cells as i32
-                                            - self.vision_ratio as i32)
-                                            % self.grid.num_cells as i32;
-                                    let index_j =
-                                        (c_j as i32 + m as i32 + self.grid.num_cells as i32
-                                            - self.vision_ratio as i32)
-                                            % self.grid.num_cells as i32;
-                                    for a_2_i in 0..self.grid.cells[index_i as usize]
-                                        [index_j as usize]
-                                        .agent_indices
-                                        .len()
-                                    {
-                                        let a_2_index = self.grid.cells[index_i as usize]
-                                            [index_j as usize]
-                                            .agent_indices[a_2_i];
-                                        let dist: f32 = distance(
-                                            &self.agents[a_1_index].positions
-                                                [self.times.current_index],
-                                            &self.agents[a_2_index].positions
-                                                [self.times.current_index],
-                                            self.bound_length,
-                                            &self.boundary_condition,
-                                        );
+                            let mut num_align = 0;
+                            let mut num_repel = 0;
+                            let mut prey_offset = Vec2::ZERO;
+                            // Cooperating predators in range and prey visible for target splitting
+                            let mut partners: Vec<usize> = Vec::new();
+                            let mut visible_prey: Vec<(f32, usize)> = Vec::new();
 
-                                        // Only chase if prey
-
-                                        if dist > 0.0000001 {
-                                            match &self.agents[a_2_index].agent_type {
-                                                AgentType::Prey(..) => {
-                                                    if dist < params.vision_radius {
-                                                        let curr_attraction = distance_vec(
-                                                            self.agents[a_1_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.agents[a_2_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.bound_length,
-                                                            &self.boundary_condition,
-                                                        );
-                                                        prey_attraction += curr_attraction
-                                                            / (curr_attraction.length_squared())
-                                                                .powf(1.5);
-                                                        num_nearby += 1;
+                            for (index_i, index_j) in
+                                self.grid.neighbour_cells(c_i, c_j, params.max_radius())
+                            {
+                                for a_2_i in 0..self.grid.cells[index_i][index_j].agent_indices.len() {
+                                    let a_2_index =
+                                        self.grid.cells[index_i][index_j].agent_indices[a_2_i];
+                                    let (seen_pos, seen_vel) = self.perceive(
+                                        a_2_index,
+                                        params.position_noise,
+                                        params.velocity_noise,
+                                        params.reaction_delay,
+                                        &mut rng,
+                                    );
+                                    let dist_vec = distance_vec(
+                                        &self.agents[a_1_index].positions
+                                            [self.times.current_index],
+                                        &seen_pos,
+                                        self.bound_length,
+                                        &self.boundary_condition,
+                                    );
+                                    let dist = dist_vec.length();
+
+                                    // Only chase if prey
+                                    if a_2_index != a_1_index && dist > 0.0000001 {
+                                        match &self.agents[a_2_index].agent_type {
+                                            AgentType::Prey(..) => {
+                                                nearest_prey = nearest_prey.min(dist);
+                                                if dist < params.chase_radius {
+                                                    prey_attraction += params.chase_kernel.weight(dist)
+                                                        * dist_vec
+                                                        / dist;
+                                                    prey_offset += dist_vec;
+                                                    num_nearby += 1;
+                                                    visible_prey.push((dist, a_2_index));
+                                                }
+                                            }
+                                            AgentType::Predator(..) => {
+                                                nearest_predator = nearest_predator.min(dist);
+                                                if let Some(coop) = &params.cooperation {
+                                                    if dist < coop.comm_radius {
+                                                        partners.push(a_2_index);
                                                     }
                                                 }
-                                                AgentType::Predator(..) => {
-                                                    if dist < params.vision_radius {
-                                                        pred_alignment += self.agents[a_2_index]
-                                                            .velocities[self.times.current_index]
+                                                if dist < params.align_radius {
+                                                    pred_alignment += params.align_kernel.weight(dist)
+                                                        * (seen_vel
                                                             - self.agents[a_1_index].velocities
-                                                                [self.times.current_index];
-                                                        let curr_repulsion = distance_vec(
-                                                            self.agents[a_1_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.agents[a_2_index]
-                                                                .positions
-                                                                .last()
-                                                                .unwrap(),
-                                                            self.bound_length,
-                                                            &self.boundary_condition,
-                                                        );
-                                                        pred_repulsion += curr_repulsion
-                                                            / curr_repulsion.length_squared();
-                                                        pred_num_nearby += 1;
-                                                    }
+                                                                [self.times.current_index]);
+                                                    num_align += 1;
+                                                }
+                                                if dist < params.repel_radius {
+                                                    pred_repulsion += params.repel_kernel.weight(dist)
+                                                        * dist_vec
+                                                        / dist;
+                                                    num_repel += 1;
                                                 }
                                             }
                                         }
                                     }
                                 }
                             }
+                            prey_attraction = params.normalisation.apply(prey_attraction, num_nearby);
+                            pred_alignment = params.normalisation.apply(pred_alignment, num_align);
+                            pred_repulsion = params.normalisation.apply(pred_repulsion, num_repel);
+
+                            // Head for where prey were last seen, or search if that is forgotten
+                            let position = self.agents[a_1_index].positions[self.times.current_index];
+                            let mut search_force = Vec2::ZERO;
                             if num_nearby > 0 {
-                                prey_attraction = prey_attraction / num_nearby as f32;
+                                hunt.see(position + prey_offset / num_nearby as f32);
+                            } else {
+                                for p in partners.iter() {
+                                    hunt.share(&self.agents[*p].hunt);
+                                }
+                                match hunt.recall(self.times.dt, params.memory_duration) {
+                                    Some(target) => {
+                                        prey_attraction = distance_vec(
+                                            &position,
+                                            &target,
+                                            self.bound_length,
+                                            &self.boundary_condition,
+                                        )
+                                        .normalize_or_zero();
+                                    }
+                                    None => {
+                                        search_force = hunt.search_direction(
+                                            &params.search,
+                                            self.agents[a_1_index].velocities[self.times.current_index],
+                                            self.times.dt,
+                                            &mut rng,
+                                        );
+                                    }
+                                }
                             }
-
-                            if pred_num_nearby > 0 {
-                                pred_alignment = pred_alignment / pred_num_nearby as f32;
-                                pred_repulsion = pred_repulsion / pred_num_nearby as f32;
+                            let mut flank_force = Vec2::ZERO;
+                            if let Some(coop) = &params.cooperation {
+                                // Chase the nearest prey no partner has claimed
+                                hunt.target = None;
+                                if coop.split_targets && !visible_prey.is_empty() {
+                                    let claimed: Vec<usize> = partners
+                                        .iter()
+                                        .filter_map(|p| self.agents[*p].hunt.target)
+                                        .collect();
+                                    visible_prey.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                                    let (_, prey_index) = *visible_prey
+                                        .iter()
+                                        .find(|(_, i)| !claimed.contains(&self.agents[*i].id))
+                                        .unwrap_or(&visible_prey[0]);
+                                    hunt.target = Some(self.agents[prey_index].id);
+                                    prey_attraction = distance_vec(
+                                        &position,
+                                        &self.agents[prey_index].positions[self.times.current_index],
+                                        self.bound_length,
+                                        &self.boundary_condition,
+                                    )
+                                    .normalize_or_zero();
+                                }
+                                // Spread out around the target by moving away, tangentially,
+                                // from partners at a similar bearing
+                                if let Some(target) = hunt.last_seen {
+                                    let bearing = distance_vec(
+                                        &target,
+                                        &position,
+                                        self.bound_length,
+                                        &self.boundary_condition,
+                                    )
+                                    .normalize_or_zero();
+                                    for p in partners.iter() {
+                                        let partner_bearing = distance_vec(
+                                            &target,
+                                            &self.agents[*p].positions[self.times.current_index],
+                                            self.bound_length,
+                                            &self.boundary_condition,
+                                        )
+                                        .normalize_or_zero();
+                                        let closeness = (1.0 + bearing.dot(partner_bearing)) / 2.0;
+                                        flank_force -= bearing.perp_dot(partner_bearing).signum()
+                                            * closeness
+                                            * bearing.perp();
+                                    }
+                                    if !partners.is_empty() {
+                                        flank_force /= partners.len() as f32;
+                                    }
+                                    flank_force *= coop.flank_weight;
+                                }
                             }
                             let mut F_j = params.prey_attraction * prey_attraction
                                 + params.predator_alignment * pred_alignment
-                                - params.predator_repulsion * pred_repulsion;
+                                - params.predator_repulsion * pred_repulsion
+                                + params.current_direction * self.preferred_heading(a_1_index)
+                                + params.search_weight * search_force
+                                + flank_force
+                                + params.pheromone_response * self.pheromone_direction(a_1_index);
                             let F_length = F_j.length();
                             bound_force = bound_force * F_length.max(params.max_acceleration);
                             F_j += bound_force;
-                            let mut rng = rand::thread_rng();
-                            let normal = Normal::new(0.0, 0.05).unwrap();
+                            let normal = Normal::new(0.0, params.noise).unwrap();
                             F_j.x += normal.sample(&mut rng);
                             F_j.y += normal.sample(&mut rng);
                             let F_length = F_j.length();
-                            F_j = F_j.normalize();
-                            F_j = F_j * (F_length.min(params.max_acceleration));
+                            if F_length > 0.00001 {
+                                F_j = F_j.normalize();
+                                F_j = F_j * (F_length.min(params.max_acceleration));
+                            } else {
+                                F_j = Vec2::ZERO;
+                            }
                             F_j
                         }
                     };
-                    let max_vel = match &self.agents[a_1_index].agent_type {
-                        AgentType::Prey(_, params) => params.max_vel,
-                        AgentType::Predator(_, params) => params.max_vel,
+                    let (max_vel, kinematics) = match agent_type.as_ref() {
+                        AgentType::Prey(_, params) => {
+                            let speed_gain =
+                                params.alarm.as_ref().map_or(0.0, |alarm| alarm.speed_gain);
+                            (
+                                params.max_vel * (1.0 + speed_gain * alarm_level),
+                                params.kinematics.clone(),
+                            )
+                        }
+                        AgentType::Predator(_, params) => {
+                            let position = &self.agents[a_1_index].positions[self.times.current_index];
+                            let slowed = self
+                                .refuges
+                                .iter()
+                                .filter(|r| r.contains(position, self.bound_length))
+                                .filter_map(|r| match r.predator_access {
+                                    PredatorAccess::Slowed(factor) => Some(factor),
+                                    _ => None,
+                                })
+                                .fold(1.0, f32::min);
+                            (params.max_vel * slowed, params.kinematics.clone())
+                        }
+                    };
+                    let drift = self.flow.drift(
+                        &self.agents[a_1_index].positions[self.times.current_index],
+                        self.bound_length,
+                    );
+                    self.agents[a_1_index].update(&self.times, F_j, max_vel, &kinematics, drift);
+                    self.agents[a_1_index].alarm.push(alarm_level);
+                    self.agents[a_1_index].hunt = hunt;
+                    let eaten = self.graze(a_1_index);
+                    self.agents[a_1_index].intake.push(eaten);
+                    self.deposit_pheromone(a_1_index);
+                    let perception = Perception {
+                        nearest_predator,
+                        nearest_prey,
+                        alarm: alarm_level,
+                        kill_cooldown: self.agents[a_1_index].kill_cooldown,
                     };
-                    self.agents[a_1_index].update(&mut self.times, F_j, max_vel);
+                    self.agents[a_1_index].advance_behaviour(self.times.dt, &perception);
 
                     match self.boundary_condition {
                         BC::Hard => {
@@ -711,9 +1387,22 @@ impl Model {
                             self.agents[a_1_index].hard_boundary(&self.times, self.bound_length);
                         }
                     }
+                    if let AgentType::Predator(..) = self.agents[a_1_index].agent_type {
+                        self.keep_out_of_refuges(a_1_index);
+                    }
                 }
             }
         }
+        self.rng = rng;
+        if self.collisions {
+            self.resolve_collisions();
+        }
+        if let Some(resource) = &mut self.resource {
+            resource.regrow(self.times.dt);
+        }
+        if let Some(pheromone) = &mut self.pheromone {
+            pheromone.update(self.bound_length, self.times.dt, matches!(self.boundary_condition, BC::Periodic));
+        }
         // Change cells if needed
         for c_i in 0..self.grid.num_cells {
             for c_j in 0..self.grid.num_cells {
@@ -751,50 +1440,43 @@ impl Model {
                     match &self.agents[a_index].agent_type {
                         AgentType::Predator(_, params) => {
                             if self.agents[a_index].kill_cooldown <= 0.0 {
-                                for n in 0..self.vision_ratio + 2 {
-                                    for m in 0..self.vision_ratio + 2 {
-                                        let index_i =
-                                            (c_i as i32 + n as i32 + self.grid.num_cells as i32
-                                                - self.vision_ratio as i32)
-                                                % self.grid.num_cells as i32;
-                                        let index_j =
-                                            (c_j as i32 + m as i32 + self.grid.num_cells as i32
-                                                - self.vision_ratio as i32)
-                                                % self.grid.num_cells as i32;
-                                        for a_2_i in 0..self.grid.cells[index_i as usize]
-                                            [index_j as usize]
-                                            .agent_indices
-                                            .len()
-                                        {
-                                            let a_2_index = self.grid.cells[index_i as usize]
-                                                [index_j as usize]
-                                                .agent_indices[a_2_i];
-                                            match &mut self.agents[a_2_index].agent_type {
-                                                AgentType::Prey(_, _) => {
-                                                    let dist: f32 = distance(
-                                                        &self.agents[a_index].positions
-                                                            [self.times.current_index],
-                                                        &self.agents[a_2_index].positions
-                                                            [self.times.current_index],
-                                                        self.bound_length,
-                                                        &self.boundary_condition,
-                                                    );
-                                                    if dist < 0.05 {
-                                                        self.grid.cells[index_i as usize][index_j as usize]
-                                                            .agent_indices
-                                                            .remove(a_2_i);
-                                                        self.agents[a_2_index].dead = State::Dead(
-                                                                self.times.current_index, 
-                                                                self.agents[a_2_index].positions[self.times.current_index].clone(),
-                                                            );
-                                                        self.agents[a_index].reset_cooldown();
-                                                        break 'outer
-                                                    }
-                                                },
-                                                _ => (),
-                                            }
-
+                                for (index_i, index_j) in self.grid.neighbour_cells(c_i, c_j, 0.05) {
+                                    for a_2_i in 0..self.grid.cells[index_i][index_j].agent_indices.len() {
+                                        let a_2_index =
+                                            self.grid.cells[index_i][index_j].agent_indices[a_2_i];
+                                        match &mut self.agents[a_2_index].agent_type {
+                                            AgentType::Prey(_, _) => {
+                                                let dist: f32 = distance(
+                                                    &self.agents[a_index].positions
+                                                        [self.times.current_index],
+                                                    &self.agents[a_2_index].positions
+                                                        [self.times.current_index],
+                                                    self.bound_length,
+                                                    &self.boundary_condition,
+                                                );
+                                                let sheltered = self.in_refuge(
+                                                    &self.agents[a_2_index].positions
+                                                        [self.times.current_index],
+                                                );
+                                                if dist < 0.05 && !sheltered {
+                                                    let event = self.kill_event(a_index, a_2_index);
+                                                    self.kills.push(event);
+                                                    self.grid.cells[index_i][index_j]
+                                                        .agent_indices
+                                                        .remove(a_2_i);
+                                                    self.agents[a_2_index].dead = State::Dead(
+                                                            self.times.current_index, 
+                                                            self.agents[a_2_index].positions[self.times.current_index].clone(),
+                                                        );
+                                                    let prey_id = self.agents[a_2_index].id;
+                                                    self.agents[a_index].kills.push(prey_id);
+                                                    self.agents[a_index].reset_cooldown();
+                                                    break 'outer
+                                                }
+                                            },
+                                            _ => (),
                                         }
+
                                     }
                                 }
                             }
@@ -809,6 +1491,283 @@ impl Model {
         }
         self.times.inc_time();
     }
+
+    fn kill_event(&self, predator_index: usize, prey_index: usize) -> KillEvent {
+        let t = self.times.current_index;
+        let prey = &self.agents[prey_index];
+        let predator = &self.agents[predator_index];
+        let radius = match &prey.agent_type {
+            AgentType::Prey(_, params) => params.max_radius(),
+            AgentType::Predator(_, params) => params.max_radius(),
+        };
+        let mut local_density = 0;
+        let mut centroid = Vec2::ZERO;
+        let mut num_alive = 0;
+        for (index, a) in self.agents.iter().enumerate() {
+            if !matches!((&a.agent_type, &a.dead), (AgentType::Prey(..), State::Alive)) {
+                continue;
+            }
+            centroid += a.positions[t];
+            num_alive += 1;
+            let dist = distance(
+                &prey.positions[t],
+                &a.positions[t],
+                self.bound_length,
+                &self.boundary_condition,
+            );
+            if index != prey_index && dist < radius {
+                local_density += 1;
+            }
+        }
+        centroid /= num_alive.max(1) as f32;
+        KillEvent {
+            time: self.times.times[t],
+            time_index: t + self.dropped_steps,
+            prey_id: prey.id,
+            predator_id: predator.id,
+            prey_position: prey.positions[t],
+            prey_velocity: prey.velocities[t],
+            predator_position: predator.positions[t],
+            predator_velocity: predator.velocities[t],
+            local_density,
+            centroid_distance: (prey.positions[t] - centroid).length(),
+        }
+    }
+
+    fn resource_direction(&self, a_index: usize) -> Vec2 {
+        match &self.resource {
+            Some(resource) => resource
+                .field
+                .gradient(
+                    &self.agents[a_index].positions[self.times.current_index],
+                    self.bound_length,
+                    matches!(self.boundary_condition, BC::Periodic),
+                )
+                .normalize_or_zero(),
+            None => Vec2::ZERO,
+        }
+    }
+
+    fn pheromone_direction(&self, a_index: usize) -> Vec2 {
+        match &self.pheromone {
+            Some(pheromone) => pheromone
+                .field
+                .gradient(
+                    &self.agents[a_index].positions[self.times.current_index],
+                    self.bound_length,
+                    matches!(self.boundary_condition, BC::Periodic),
+                )
+                .normalize_or_zero(),
+            None => Vec2::ZERO,
+        }
+    }
+
+    fn deposit_pheromone(&mut self, a_index: usize) {
+        let deposit = match &self.agents[a_index].agent_type {
+            AgentType::Prey(_, params) => params.pheromone_deposit,
+            AgentType::Predator(_, params) => params.pheromone_deposit,
+        };
+        if let Some(pheromone) = &mut self.pheromone {
+            if deposit > 0.0 {
+                pheromone.field.add(
+                    &self.agents[a_index].positions[self.times.current_index],
+                    self.bound_length,
+                    deposit * self.times.dt,
+                );
+            }
+        }
+    }
+
+    // Eat from the resource cell under a prey, returning the amount eaten
+    fn graze(&mut self, a_index: usize) -> f32 {
+        let intake_rate = match &self.agents[a_index].agent_type {
+            AgentType::Prey(_, params) => params.intake_rate,
+            AgentType::Predator(..) => return 0.0,
+        };
+        match &mut self.resource {
+            Some(resource) if intake_rate > 0.0 => resource.field.take(
+                &self.agents[a_index].positions[self.times.current_index],
+                self.bound_length,
+                intake_rate * self.times.dt,
+            ),
+            _ => 0.0,
+        }
+    }
+
+    pub fn in_refuge(&self, pos: &Vec2) -> bool {
+        self.refuges.iter().any(|r| r.contains(pos, self.bound_length))
+    }
+
+    fn refuge_direction(&self, a_index: usize) -> Vec2 {
+        let pos = &self.agents[a_index].positions[self.times.current_index];
+        match refuge::nearest(&self.refuges, pos, self.bound_length) {
+            Some(r) => r.direction(pos, self.bound_length),
+            None => Vec2::ZERO,
+        }
+    }
+
+    // Predators stop at the edge of refuges they may not enter. One already inside, e.g.
+    // spawned there, is free to leave.
+    fn keep_out_of_refuges(&mut self, a_index: usize) {
+        let current = self.times.current_index;
+        let next = current + 1;
+        let blocked = self.refuges.iter().any(|r| {
+            matches!(r.predator_access, PredatorAccess::Closed)
+                && !r.contains(&self.agents[a_index].positions[current], self.bound_length)
+                && r.contains(&self.agents[a_index].positions[next], self.bound_length)
+        });
+        if blocked {
+            self.agents[a_index].positions[next] = self.agents[a_index].positions[current];
+            self.agents[a_index].velocities[next] = Vec2::ZERO;
+        }
+    }
+
+    // Soft boundary force direction scaled by a species' avoidance strength
+    fn wall_force(&self, a_index: usize, strength: f32, range: Option<f32>) -> Vec2 {
+        match self.boundary_condition {
+            BC::Soft(br) => {
+                strength
+                    * soft_boundary(
+                        &self.agents[a_index].positions[self.times.current_index],
+                        self.bound_length,
+                        range.unwrap_or(br),
+                    )
+            }
+            _ => Vec2::ZERO,
+        }
+    }
+
+    // Position and velocity of an agent as seen by a neighbour, reaction_delay steps ago
+    // and with Gaussian error
+    fn perceive<R: Rng>(
+        &self,
+        a_index: usize,
+        position_noise: f32,
+        velocity_noise: f32,
+        reaction_delay: usize,
+        rng: &mut R,
+    ) -> (Vec2, Vec2) {
+        let index = self.times.current_index.saturating_sub(reaction_delay);
+        let mut position = self.agents[a_index].positions[index];
+        let mut velocity = self.agents[a_index].velocities[index];
+        if position_noise > 0.0 {
+            let normal = Normal::new(0.0, position_noise).unwrap();
+            position += Vec2::new(normal.sample(rng), normal.sample(rng));
+        }
+        if velocity_noise > 0.0 {
+            let normal = Normal::new(0.0, velocity_noise).unwrap();
+            velocity += Vec2::new(normal.sample(rng), normal.sample(rng));
+        }
+        (position, velocity)
+    }
+
+    fn preferred_heading(&self, a_index: usize) -> Vec2 {
+        self.flow.preferred_heading(
+            &self.agents[a_index].positions[self.times.current_index],
+            &self.agents[a_index].velocities[self.times.current_index],
+            self.bound_length,
+        )
+    }
+
+    // Push overlapping bodies apart on the positions for the next time step. Agents with
+    // body_radius 0.0 are points and never collide.
+    fn resolve_collisions(&mut self) {
+        let max_body = self
+            .agents
+            .iter()
+            .map(|a| a.body_radius())
+            .fold(0.0, f32::max);
+        if max_body <= 0.0 {
+            return;
+        }
+        let next_index = self.times.current_index + 1;
+        for _ in 0..COLLISION_ITERATIONS {
+            let mut overlapping = false;
+            for c_i in 0..self.grid.num_cells {
+                for c_j in 0..self.grid.num_cells {
+                    for a_1_i in 0..self.grid.cells[c_i][c_j].agent_indices.len() {
+                        let a_1_index = self.grid.cells[c_i][c_j].agent_indices[a_1_i];
+                        let r_1 = self.agents[a_1_index].body_radius();
+                        if r_1 <= 0.0 {
+                            continue;
+                        }
+                        for (index_i, index_j) in
+                            self.grid.neighbour_cells(c_i, c_j, r_1 + max_body)
+                        {
+                            for a_2_i in 0..self.grid.cells[index_i][index_j].agent_indices.len() {
+                                let a_2_index =
+                                    self.grid.cells[index_i][index_j].agent_indices[a_2_i];
+                                // Each pair once
+                                if a_2_index <= a_1_index {
+                                    continue;
+                                }
+                                let r_2 = self.agents[a_2_index].body_radius();
+                                if r_2 <= 0.0 {
+                                    continue;
+                                }
+                                let dist_vec = distance_vec(
+                                    &self.agents[a_1_index].positions[next_index],
+                                    &self.agents[a_2_index].positions[next_index],
+                                    self.bound_length,
+                                    &self.boundary_condition,
+                                );
+                                let dist = dist_vec.length();
+                                let overlap = r_1 + r_2 - dist;
+                                if overlap <= 0.0 {
+                                    continue;
+                                }
+                                overlapping = true;
+                                let direction = if dist > 0.0000001 {
+                                    dist_vec / dist
+                                } else {
+                                    random_heading(&mut self.rng)
+                                };
+                                self.agents[a_1_index].positions[next_index] -=
+                                    0.5 * overlap * direction;
+                                self.agents[a_2_index].positions[next_index] +=
+                                    0.5 * overlap * direction;
+                            }
+                        }
+                    }
+                }
+            }
+            if !overlapping {
+                break;
+            }
+        }
+        // Pushes can move agents over the boundary
+        for agent in self.agents.iter_mut() {
+            if !matches!(agent.dead, State::Alive) || agent.positions.len() <= next_index {
+                continue;
+            }
+            match self.boundary_condition {
+                BC::Periodic => agent.periodic_boundary(&self.times, self.bound_length),
+                _ => agent.hard_boundary(&self.times, self.bound_length),
+            }
+        }
+    }
+
+    fn draw_fields(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) {
+        if let Some(resource) = &self.resource {
+            resource.field.draw(
+                ctx,
+                canvas,
+                self.bound_length,
+                self.scale,
+                RESOURCE,
+                resource.max_capacity(),
+            );
+        }
+        if let Some(pheromone) = &self.pheromone {
+            let max = pheromone.field.values.iter().cloned().fold(0.0, f32::max);
+            if max > 0.0 {
+                pheromone
+                    .field
+                    .draw(ctx, canvas, self.bound_length, self.scale, PHEROMONE, max);
+            }
+        }
+    }
+
     // Draw model for current time step
     pub fn draw(
         &mut self,
@@ -816,7 +1775,9 @@ impl Model {
         canvas: &mut graphics::Canvas,
         disco_mode: &PlayState,
     ) {
+        self.draw_fields(ctx, canvas);
         for a_index in 0..self.agents.len() {
+            self.agents[a_index].ensure_polygon(ctx, self.scale);
             let new_colour;
             match self.agents[a_index].agent_type {
                 AgentType::Prey(_, _) => new_colour = CREAM,
@@ -825,7 +1786,29 @@ impl Model {
             self.agents[a_index].agent_type = (self.agents[a_index].agent_type)
                 .clone()
                 .change_colour(new_colour);
-            self.agents[a_index].draw(ctx, canvas, self.scale, &disco_mode, 0, 1.0);
+            self.agents[a_index].draw(canvas, self.scale, disco_mode, 0, 1.0);
+        }
+    }
+
+    // Draw a saved run as it was at time step t. Agents are shown from birth until death.
+    pub fn draw_replay(
+        &mut self,
+        ctx: &mut Context,
+        canvas: &mut graphics::Canvas,
+        disco_mode: &PlayState,
+        t: usize,
+    ) {
+        self.draw_fields(ctx, canvas);
+        for agent in self.agents.iter_mut() {
+            agent.ensure_polygon(ctx, self.scale);
+            let died = match agent.dead {
+                State::Dead(index, _) => index <= t,
+                State::Alive => false,
+            };
+            if died || t < agent.birth_index || t >= agent.positions.len() {
+                continue;
+            }
+            agent.draw_at(canvas, self.scale, disco_mode, t, 1.0);
         }
     }
 
@@ -848,7 +1831,9 @@ impl Model {
        //let transparent = [
         //    1.0, 0.5, 0.45, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
         //];
+        self.draw_fields(ctx, canvas);
         for a_index in 0..self.agents.len() {
+            self.agents[a_index].ensure_polygon(ctx, self.scale);
             for i in 0 as usize..10 as usize {
                 let offset = i * 40;
                 let mut new_colour;
@@ -861,7 +1846,7 @@ impl Model {
                 self.agents[a_index].agent_type = (self.agents[a_index].agent_type)
                     .clone()
                     .change_colour(new_colour);
-                self.agents[a_index].draw(ctx, canvas, self.scale, &disco_mode, offset, transparent[i]);
+                self.agents[a_index].draw(canvas, self.scale, disco_mode, offset, transparent[i]);
             }
         }
     }
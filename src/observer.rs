@@ -0,0 +1,67 @@
+use crate::model::{KillEvent, Model};
+use std::error::Error;
+use std::fs::File;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Continue,
+    Stop,
+}
+
+// Hooks called by Model::step, so they also fire when the viewer steps the model. The
+// model passed to each callback has already advanced, so model.times.current_index is the
+// step that has just been computed. on_run_end is only called by Model::run.
+pub trait Observer {
+    fn on_step(&mut self, _model: &Model) -> Control {
+        Control::Continue
+    }
+
+    fn on_kill(&mut self, _model: &Model, _kill: &KillEvent) -> Control {
+        Control::Continue
+    }
+
+    fn on_run_end(&mut self, _model: &Model) {}
+}
+
+// Any closure over the model can be used as a step observer
+impl<F: FnMut(&Model) -> Control> Observer for F {
+    fn on_step(&mut self, model: &Model) -> Control {
+        self(model)
+    }
+}
+
+// Column name and the value written for it each step
+pub type Metric = (String, fn(&Model) -> f32);
+
+// Streams named metrics to a CSV file, one row per step
+pub struct CsvObserver {
+    writer: csv::Writer<File>,
+    metrics: Vec<Metric>,
+}
+
+impl CsvObserver {
+    pub fn new(path: &str, metrics: Vec<Metric>) -> Result<CsvObserver, Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        let mut header = vec!["time".to_owned()];
+        header.extend(metrics.iter().map(|(name, _)| name.clone()));
+        writer.write_record(&header)?;
+        Ok(CsvObserver { writer, metrics })
+    }
+}
+
+impl Observer for CsvObserver {
+    fn on_step(&mut self, model: &Model) -> Control {
+        let mut row = vec![model.times.times[model.times.current_index].to_string()];
+        row.extend(self.metrics.iter().map(|(_, f)| f(model).to_string()));
+        if let Err(e) = self.writer.write_record(&row) {
+            eprintln!("{}", e);
+        }
+        Control::Continue
+    }
+
+    fn on_run_end(&mut self, _model: &Model) {
+        if let Err(e) = self.writer.flush() {
+            eprintln!("{}", e);
+        }
+    }
+}
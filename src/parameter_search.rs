@@ -1,15 +1,45 @@
 use optimize::{Minimizer, NelderMeadBuilder};
 use crate::boid::{PredParams, PreyParams};
+use crate::field::Resource;
 use crate::model::{Model, Parameters, Time, BC};
+use crate::stopping::StopCondition;
 use crate::plot::*;
 use ndarray::prelude::*;
 use std::time::Duration;
 use serde::{Serialize,Deserialize};
+use std::error::Error;
 use std::fs::File;
 use std::io::Write;
 
 use cmaes::{CMAESOptions, DVector, PlotOptions, restart};
 
+// Food and the prey's fixed foraging parameters, shared by every model the optimisers run
+#[derive(Debug, Clone)]
+pub struct Foraging {
+    pub resource: Option<Resource>,
+    pub forage_weight: f32,
+    pub intake_rate: f32,
+}
+
+impl Foraging {
+    pub fn none() -> Foraging {
+        Foraging {
+            resource: None,
+            forage_weight: 0.0,
+            intake_rate: 0.0,
+        }
+    }
+
+    // Take the resource and prey foraging parameters from a scenario
+    pub fn from(parameters: &Parameters) -> Foraging {
+        Foraging {
+            resource: parameters.resource.clone(),
+            forage_weight: parameters.prey_params.forage_weight,
+            intake_rate: parameters.prey_params.intake_rate,
+        }
+    }
+}
+
 // x[0] = prey_alignment
 // x[1] = prey_attraction
 // x[2] = prey_repulsion
@@ -17,38 +47,31 @@ use cmaes::{CMAESOptions, DVector, PlotOptions, restart};
 // x[4] = predator_repulsion
 //pub fn prey_optimise(x: ArrayView1<f64>) -> f64 {
 pub fn prey_optimise(x: &DVector<f64>, behaviour_params: &Vec<f64>,
-    physical_params: &Vec<f32>, space_params: &Vec<f32>) -> f64 {
+    physical_params: &Vec<f32>, space_params: &Vec<f32>, foraging: &Foraging,
+    objective: &Objective) -> f64 {
     // If out of bounds punish
     if x[0] > 1.0 || x[1] > 1.0 || x[2] > 1.0 || x[3] > 1.0 || x[4] > 1.0 ||
         x[0] < -1.0 || x[1] < -1.0 || x[2] < -1.0 || x[3] < -1.0 || x[4] < -1.0 {
         return 1.0
     }
     let prey_params = PreyParams {
-        vision_radius: 1.0,
-        current_direction: 0.0, // not in use
         prey_alignment: x[0] as f32,
         prey_attraction: x[1] as f32,
         prey_repulsion: x[2] as f32,
         predator_alignment: x[3] as f32,
-        predator_centering: 0.0,
         predator_repulsion: x[4] as f32,
-        max_acceleration: 2.0,
-        max_vel: 1.0,
-        boundary: 20.0, // not in use
+        forage_weight: foraging.forage_weight,
+        intake_rate: foraging.intake_rate,
+        ..PreyParams::new()
     };
     let pred_params = PredParams {
-        vision_radius: 2.0,
-        current_direction: 0.0, // not in use
-        prey_alignment: 0.0,
         prey_attraction: behaviour_params[0] as f32, //0.1813,
-        nearest_prey: 0.0, // not in use
         predator_alignment: behaviour_params[1] as f32, //0.5662,
         predator_attraction: behaviour_params[2] as f32, //0.1217,
         predator_repulsion: behaviour_params[3] as f32, //0.0315,
         max_acceleration: physical_params[0],
         max_vel: physical_params[1],
-        boundary: 20.0, //not in use
-        cooldown: 0.5,
+        ..PredParams::new()
     };
     let params = Parameters {
         // Model
@@ -59,46 +82,41 @@ pub fn prey_optimise(x: &DVector<f64>, behaviour_params: &Vec<f64>,
         times: Time::new(1.0 / 20.0, 300.0),
         prey_params,
         pred_params,
+        resource: foraging.resource.clone(),
+        stop_conditions: vec![StopCondition::AllPreyDead],
+        ..Parameters::default()
     };
     //let mut model = Model::from(&params);
     //model.run();
     //final_prop_dead(&model) as f64
-    death_distribution(params, 30, false)
+    objective_distribution(params, 30, objective)
 }
 
 pub fn pred_optimise(x: &DVector<f64>, behaviour_params: &Vec<f64>,
-    physical_params: &Vec<f32>, space_params: &Vec<f32>) -> f64 {
+    physical_params: &Vec<f32>, space_params: &Vec<f32>, foraging: &Foraging) -> f64 {
     // If out of bounds punish
     if x[0] > 1.0 || x[1] > 1.0 || x[2] > 1.0 || x[3] > 1.0 ||
        x[0] < -1.0 || x[1] < -1.0 || x[2] < -1.0 || x[3] < -1.0 {
        return 1.0
     }
     let prey_params = PreyParams {
-        vision_radius: 1.0,
-        current_direction: 0.0, // not in use
         prey_alignment: behaviour_params[0] as f32, //0.9582261937705074,
         prey_attraction: behaviour_params[1] as f32, //-0.0711222698745498,
         prey_repulsion: behaviour_params[2] as f32, //0.9824784427027915,
         predator_alignment: behaviour_params[3] as f32, //0.6863455709757276,
-        predator_centering: 0.0,
         predator_repulsion: behaviour_params[4] as f32, //0.7396519317096918,
-        max_acceleration: 2.0,
-        max_vel: 1.0,
-        boundary: 20.0, // not in use
+        forage_weight: foraging.forage_weight,
+        intake_rate: foraging.intake_rate,
+        ..PreyParams::new()
     };
     let pred_params = PredParams {
-        vision_radius: 2.0,
-        current_direction: 0.0, // not in use
-        prey_alignment: 0.0,
         prey_attraction: x[0] as f32,
-        nearest_prey: 0.0, // not in use
         predator_alignment: x[1] as f32,
         predator_attraction: x[2] as f32,
         predator_repulsion: x[3] as f32,
         max_acceleration: physical_params[0],
         max_vel: physical_params[1],
-        boundary: 20.0, //not in use
-        cooldown: 0.5,
+        ..PredParams::new()
     };
     let params = Parameters {
         // Model
@@ -109,6 +127,9 @@ pub fn pred_optimise(x: &DVector<f64>, behaviour_params: &Vec<f64>,
         times: Time::new(1.0 / 20.0, 300.0),
         prey_params,
         pred_params,
+        resource: foraging.resource.clone(),
+        stop_conditions: vec![StopCondition::AllPreyDead],
+        ..Parameters::default()
     };
     //let mut model = Model::from(&params);
     //model.run();
@@ -116,7 +137,7 @@ pub fn pred_optimise(x: &DVector<f64>, behaviour_params: &Vec<f64>,
     1.0 - death_distribution(params, 30, false)
 }
 
-fn mean(data: &[f32]) -> Option<f32> {
+pub fn mean(data: &[f32]) -> Option<f32> {
     let sum = data.iter().sum::<f32>() as f32;
     let count = data.len();
 
@@ -126,7 +147,7 @@ fn mean(data: &[f32]) -> Option<f32> {
     }
 }
 
-fn std_deviation(data: &[f32]) -> Option<f32> {
+pub fn std_deviation(data: &[f32]) -> Option<f32> {
     match (mean(data), data.len()) {
         (Some(data_mean), count) if count > 0 => {
             let variance = data.iter().map(|value| {
@@ -141,12 +162,39 @@ fn std_deviation(data: &[f32]) -> Option<f32> {
     }
 }
 
+// Quantity minimised by the optimisers, averaged over repeated runs
+#[derive(Debug, Clone)]
+pub enum Objective {
+    // Proportion of prey dead at the end
+    Deaths,
+    // Negative mean intake per prey
+    Intake,
+    // Deaths minus intake_weight times mean intake
+    DeathsAndIntake(f32),
+}
+
+pub fn objective_distribution(params: Parameters, num_iter: usize, objective: &Objective) -> f64 {
+    let mut results: Vec<f32> = Vec::new();
+    for _ in 0..num_iter {
+        let mut model = Model::from(&params);
+        model.run();
+        let result = match objective {
+            Objective::Deaths => final_prop_dead(&model),
+            Objective::Intake => -mean_intake(&model),
+            Objective::DeathsAndIntake(w) => final_prop_dead(&model) - w * mean_intake(&model),
+        };
+        results.push(result);
+    }
+    mean(&results).unwrap() as f64
+}
+
 pub fn death_distribution(params: Parameters, num_iter: usize, verbose: bool) -> f64 {
     let mut results: Vec<f32> = Vec::new();
     for i in 0..num_iter {
         if verbose { println!("Running model {}", i); }
         let mut model = Model::from(&params);
-        model.run();
+        let reason = model.run();
+        if verbose { println!("Stopped: {:?} at t = {}", reason, model.times.times[model.times.current_index]); }
         let prop_dead: f32 = final_prop_dead(&model);
         results.push(prop_dead);
     }
@@ -206,6 +254,12 @@ pub fn death_distribution(params: Parameters, num_iter: usize, verbose: bool) ->
 
 pub fn optimise_deaths_prey(behaviour_params: &Vec<f64>, physical_params: &Vec<f32>,
     space_params: &Vec<f32>) -> (DVector<f64>, f64) {
+    optimise_prey(behaviour_params, physical_params, space_params, &Foraging::none(),
+        &Objective::Deaths)
+}
+
+pub fn optimise_prey(behaviour_params: &Vec<f64>, physical_params: &Vec<f32>,
+    space_params: &Vec<f32>, foraging: &Foraging, objective: &Objective) -> (DVector<f64>, f64) {
     let dim = 5;
     let strategy = restart::RestartStrategy::BIPOP(Default::default());
     let restarter = restart::RestartOptions::new(dim, -1.0..=1.0, strategy)
@@ -215,7 +269,8 @@ pub fn optimise_deaths_prey(behaviour_params: &Vec<f64>, physical_params: &Vec<f
         .build()
         .unwrap();
 
-    let func = |x: &DVector<f64>| prey_optimise(&x, behaviour_params, physical_params, space_params);
+    let func = |x: &DVector<f64>| prey_optimise(x, behaviour_params, physical_params, space_params, foraging,
+        objective);
     let results = restarter.run_parallel(|| func);
     match results.best {
         Some(best_params) => {
@@ -226,7 +281,7 @@ pub fn optimise_deaths_prey(behaviour_params: &Vec<f64>, physical_params: &Vec<f
 }
 
 pub fn optimise_deaths_pred(behaviour_params: &Vec<f64>, physical_params: &Vec<f32>,
-    space_params: &Vec<f32>) -> (DVector<f64>, f64) {
+    space_params: &Vec<f32>, foraging: &Foraging) -> (DVector<f64>, f64) {
     let dim = 4;
     let strategy = restart::RestartStrategy::BIPOP(Default::default());
     let restarter = restart::RestartOptions::new(dim, -1.0..=1.0, strategy)
@@ -236,7 +291,7 @@ pub fn optimise_deaths_pred(behaviour_params: &Vec<f64>, physical_params: &Vec<f
         .build()
         .unwrap();
 
-    let func = |x: &DVector<f64>| pred_optimise(&x, behaviour_params, physical_params, space_params);
+    let func = |x: &DVector<f64>| pred_optimise(x, behaviour_params, physical_params, space_params, foraging);
     let results = restarter.run_parallel(|| func);
     match results.best {
         Some(best_params) => {
@@ -246,48 +301,58 @@ pub fn optimise_deaths_pred(behaviour_params: &Vec<f64>, physical_params: &Vec<f
     }
 }
 
-pub fn optimise_regime() {
+pub fn optimise_regime() -> std::result::Result<(), Box<dyn Error>> {
     //let regimes: Vec<Vec<f32>> = vec![vec![2.0,1.0], vec![3.0,1.25], vec![1.0, 0.75]];
     //let scenarios: Vec<Vec<f32>> = vec![vec![10.0,3.0], vec![20.0,12.0], vec![30.0,27.0]];
     let regimes: Vec<Vec<f32>> = vec![vec![3.0,1.25]];
     let scenarios: Vec<Vec<f32>> = vec![vec![10.0,3.0], vec![20.0,12.0], vec![30.0,27.0]];
     for physical_params in regimes.iter() {
         for space_params in scenarios.iter() {
-            let mut output = Result::new();
-            for i in 0..4 {
-                // prey optimisation
-                let result = optimise_deaths_prey(
-                    &output.pred_behaviour_params.last().unwrap(),
-                    &physical_params,
-                    &space_params
-                );
-                output.prey_behaviour_params.push(result.0.iter().copied().collect::<Vec<f64>>().clone());
-                output.final_predation.push(result.1);
-                println!("{:?}, {:?}, {:?}", output.prey_behaviour_params,
-                    output.pred_behaviour_params, output.final_predation);
-
-                // predator optimisation
-                let result = optimise_deaths_pred(
-                    &output.prey_behaviour_params.last().unwrap(),
-                    &physical_params,
-                    &space_params
-                );
-                output.pred_behaviour_params.push(result.0.iter().copied().collect::<Vec<f64>>());
-                output.final_predation.push(result.1);
-                println!("{:?}, {:?}, {:?}", output.prey_behaviour_params,
-                    output.pred_behaviour_params, output.final_predation);
-            }
+            let output = co_evolve(physical_params, space_params, 4, &Foraging::none(),
+                &Objective::Deaths);
             // create file for results
-            let mut file = File::create(
-                String::from("results_") +
+            let path = String::from("results_") +
                 &physical_params[0].to_string() +
                 &space_params[0].to_string() +
-                &".json".to_string()
-            ).expect("Failed to create file");
-            let serialized = serde_json::to_string(&output).expect("Failed to serialize to JSON");
-            file.write_all(serialized.as_bytes()).expect("Failed to write to file");
+                &".json".to_string();
+            output.save(&path)?;
         }
     }
+    Ok(())
+}
+
+// Alternately optimise prey against the latest predators and predators against the latest
+// prey, rounds times each. Prey minimise objective, predators always maximise deaths.
+pub fn co_evolve(physical_params: &Vec<f32>, space_params: &Vec<f32>, rounds: usize,
+    foraging: &Foraging, objective: &Objective) -> Result {
+    let mut output = Result::new();
+    for _ in 0..rounds {
+        // prey optimisation
+        let result = optimise_prey(
+            output.pred_behaviour_params.last().unwrap(),
+            physical_params,
+            space_params,
+            foraging,
+            objective
+        );
+        output.prey_behaviour_params.push(result.0.iter().copied().collect::<Vec<f64>>().clone());
+        output.final_scores.push(result.1);
+        println!("{:?}, {:?}, {:?}", output.prey_behaviour_params,
+            output.pred_behaviour_params, output.final_scores);
+
+        // predator optimisation
+        let result = optimise_deaths_pred(
+            output.prey_behaviour_params.last().unwrap(),
+            physical_params,
+            space_params,
+            foraging
+        );
+        output.pred_behaviour_params.push(result.0.iter().copied().collect::<Vec<f64>>());
+        output.final_scores.push(result.1);
+        println!("{:?}, {:?}, {:?}", output.prey_behaviour_params,
+            output.pred_behaviour_params, output.final_scores);
+    }
+    output
 }
 
 #[derive(Debug)]
@@ -295,7 +360,9 @@ pub fn optimise_regime() {
 pub struct Result {
     pub prey_behaviour_params: Vec<Vec<f64>>,
     pub pred_behaviour_params: Vec<Vec<f64>>,
-    pub final_predation: Vec<f64>,
+    // Best value after each optimisation, alternating the prey objective and predator deaths
+    #[serde(alias = "final_predation")]
+    pub final_scores: Vec<f64>,
 }
 
 impl Result {
@@ -303,7 +370,14 @@ impl Result {
         Result {
             prey_behaviour_params: vec![vec![0.0;5]],
             pred_behaviour_params: vec![vec![0.0;4]],
-            final_predation: Vec::new(),
+            final_scores: Vec::new(),
         }
     }
+
+    pub fn save(&self, path: &str) -> std::result::Result<(), Box<dyn Error>> {
+        let mut file = File::create(path)?;
+        let serialized = serde_json::to_string(self)?;
+        file.write_all(serialized.as_bytes())?;
+        Ok(())
+    }
 }
@@ -1,7 +1,7 @@
 use plotters::prelude::*;
 use crate::boid::{Agent, AgentType, PreyParams,State, PredParams};
 use crate::graphics::CREAM;
-use crate::model::{Model, Parameters,BC,Time};
+use crate::model::{KillEvent, Model, Parameters,Time};
 use ggez::glam::Vec2;
 use std::error::Error;
 use dbscan::Classification::*;
@@ -122,10 +122,14 @@ pub fn angular_velocity(model: &Model) {
     // }
 }
 
+pub fn prey(model: &Model) -> impl Iterator<Item = &Agent> {
+    model.agents.iter().filter(|a| matches!(a.agent_type, AgentType::Prey(..)))
+}
+
 pub fn final_prop_dead(model: &Model) -> f32 {
     let mut count: u32 = 0;
-    for i in 0..model.num_prey {
-        match model.agents[i].dead {
+    for a in prey(model) {
+        match a.dead {
             State::Alive => (),
             State::Dead(index,_) => {
                 count += 1;
@@ -137,8 +141,8 @@ pub fn final_prop_dead(model: &Model) -> f32 {
 
 pub fn death_positions(model: &Model) -> Vec<Vec<f32>> {
     let mut positions = Vec::new();
-    for i in 0..model.num_prey {
-        match model.agents[i].dead {
+    for a in prey(model) {
+        match a.dead {
             State::Alive => (),
             State::Dead(_,pos) => {
                 positions.push(pos.to_array().to_vec());
@@ -151,9 +155,9 @@ pub fn death_positions(model: &Model) -> Vec<Vec<f32>> {
 pub fn plot_prey_alive(model: &Model) {
     let num_steps = model.times.times.len();
     let mut death_index: Vec<usize> = Vec::new();
-    for i in 0..model.num_prey {
-        println!("{}",i);
-        match model.agents[i].dead {
+    for a in prey(model) {
+        println!("{}",a.id);
+        match a.dead {
             State::Alive => (),
             State::Dead(index,_) => {
                 death_index.push(index);
@@ -252,8 +256,6 @@ pub fn random_params_prey(prey_max: f32, pred_max: f32, max_time: f32) -> Parame
     let mut rng = rand::thread_rng();
 
     let prey_params = PreyParams {
-        vision_radius: 1.0,
-        current_direction: 0.0, // not in use
         prey_alignment: rng.gen_range(0.0..prey_max),
         prey_attraction: rng.gen_range(0.0..prey_max),
         prey_repulsion: rng.gen_range(0.0..prey_max),
@@ -261,32 +263,372 @@ pub fn random_params_prey(prey_max: f32, pred_max: f32, max_time: f32) -> Parame
         predator_centering: rng.gen_range(0.0..pred_max),
         predator_repulsion: rng.gen_range(0.0..pred_max),
         max_acceleration: 1.0,
-        max_vel: 1.0,
-        boundary: 20.0, // not in use
+        ..PreyParams::new()
     };
     let pred_params = PredParams {
-        vision_radius: 3.0,
-        current_direction: 0.0, // not in use
-        prey_alignment: 0.0,
+        chase_radius: 3.0,
+        align_radius: 3.0,
+        attract_radius: 3.0,
+        repel_radius: 3.0,
         prey_attraction: 5.0,
-        nearest_prey: 0.0, // not in use
         predator_alignment: 1.0,
         predator_attraction: 2.0,
         predator_repulsion: 2.0,
         max_acceleration: 1.0,
         max_vel: 0.75,
-        boundary: 20.0, //not in use
-        cooldown: 0.5,
+        ..PredParams::new()
     };
     let params = Parameters {
         // Model
-        num_prey: 200,
         num_pred: 5,
         bound_length: 20.0,
-        boundary_condition: BC::Soft(0.5), // only current BC
         times: Time::new(1.0 / 60.0, max_time),
         prey_params,
         pred_params,
+        ..Parameters::default()
     };
     params
 }
+
+// Time from birth until an informed-goal target is first reached, None if never reached
+// or the agent's species has no target
+pub fn time_to_target(model: &Model, agent: &Agent) -> Option<f32> {
+    let goal = match &agent.agent_type {
+        AgentType::Prey(_, params) => params.goal.as_ref()?,
+        AgentType::Predator(..) => return None,
+    };
+    for i in agent.birth_index..agent.positions.len() {
+        if goal.reached(&agent.positions[i], model.bound_length(), &model.boundary_condition) {
+            return Some(model.times.times[i] - model.times.times[agent.birth_index]);
+        }
+    }
+    None
+}
+
+pub fn fraction_reached_target(model: &Model) -> f32 {
+    let reached = prey(model).filter(|a| time_to_target(model, a).is_some()).count();
+    reached as f32 / model.num_prey as f32
+}
+
+pub fn mean_time_to_target(model: &Model) -> Option<f32> {
+    let times: Vec<f32> = prey(model).filter_map(|a| time_to_target(model, a)).collect();
+    if times.is_empty() {
+        return None;
+    }
+    Some(times.iter().sum::<f32>() / times.len() as f32)
+}
+
+// Mean cosine between each living prey's heading and its goal direction at a time step,
+// 1.0 when the whole group moves the way the informed individuals prefer
+pub fn goal_accuracy(model: &Model, time_step: usize) -> f32 {
+    let mut total = 0.0;
+    let mut count = 0;
+    for a in prey(model) {
+        if time_step < a.birth_index || time_step >= a.positions.len() {
+            continue;
+        }
+        if let AgentType::Prey(_, params) = &a.agent_type {
+            if let Some(goal) = &params.goal {
+                let dir = goal.direction(
+                    &a.positions[time_step],
+                    model.bound_length(),
+                    &model.boundary_condition,
+                );
+                total += a.velocities[time_step].normalize_or_zero().dot(dir);
+                count += 1;
+            }
+        }
+    }
+    if count == 0 {
+        return 0.0;
+    }
+    total / count as f32
+}
+
+// One row per prey: id, informed (0/1), time to target (-1 if never reached)
+pub fn output_informed(path: String, model: &Model) {
+    let mut ids = Vec::new();
+    let mut informed = Vec::new();
+    let mut times = Vec::new();
+    for a in prey(model) {
+        ids.push(a.id as f32);
+        informed.push(if a.informed { 1.0 } else { 0.0 });
+        times.push(time_to_target(model, a).unwrap_or(-1.0));
+    }
+    if let Err(e) = write_to_file(path, vec![ids, informed, times]) {
+        eprintln!("{}", e);
+    }
+}
+
+// Alarm level of every prey at each time step, for visualising startle cascades.
+// Columns are time then one column per prey, padded with -1 before birth and after death.
+pub fn output_alarm(path: String, model: &Model) {
+    let num_steps = model.times.times.len();
+    let mut values = vec![model.times.times.clone()];
+    for a in prey(model) {
+        let mut levels = vec![-1.0; a.birth_index];
+        levels.extend_from_slice(&a.alarm[a.birth_index..]);
+        levels.resize(num_steps, -1.0);
+        values.push(levels);
+    }
+    if let Err(e) = write_to_file(path, values) {
+        eprintln!("{}", e);
+    }
+}
+
+// Fraction of living prey with alarm above a threshold at each time step
+pub fn alarmed_fraction(model: &Model, threshold: f32) -> Vec<f32> {
+    let mut fractions = Vec::new();
+    for i in 0..model.times.times.len() {
+        let mut alive = 0;
+        let mut alarmed = 0;
+        for a in prey(model) {
+            if i < a.birth_index || i >= a.alarm.len() {
+                continue;
+            }
+            alive += 1;
+            if a.alarm[i] > threshold {
+                alarmed += 1;
+            }
+        }
+        fractions.push(if alive > 0 { alarmed as f32 / alive as f32 } else { 0.0 });
+    }
+    fractions
+}
+
+// One row per agent: id, species, death time (-1 if alive at the end), then one column per
+// trait named in Parameters::prey_variation or pred_variation, empty where the agent's
+// species does not vary that trait
+pub fn output_traits(path: String, model: &Model) -> Result<(), Box<dyn Error>> {
+    let mut names: Vec<&String> = Vec::new();
+    for (name, _) in model.prey_variation.iter().chain(model.pred_variation.iter()) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let mut writer = csv::Writer::from_path(path)?;
+    let mut header = vec!["id".to_owned(), "species".to_owned(), "death_time".to_owned()];
+    header.extend(names.iter().map(|name| name.to_string()));
+    writer.write_record(&header)?;
+    for a in model.agents.iter() {
+        let species = match a.agent_type {
+            AgentType::Prey(..) => "prey",
+            AgentType::Predator(..) => "predator",
+        };
+        let death_time = match a.dead {
+            State::Alive => -1.0,
+            State::Dead(index, _) => model.times.times[index],
+        };
+        let mut row = vec![a.id.to_string(), species.to_owned(), death_time.to_string()];
+        for name in names.iter() {
+            row.push(match a.traits.iter().find(|(trait_name, _)| trait_name == *name) {
+                Some((_, value)) => value.to_string(),
+                None => String::new(),
+            });
+        }
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Behavioural state of every agent at each time step, encoded by its position in the
+// Behaviour enum. Columns are time then one column per agent, padded with -1.
+pub fn output_behaviour(path: String, model: &Model) {
+    let num_steps = model.times.times.len();
+    let mut values = vec![model.times.times.clone()];
+    for a in model.agents.iter() {
+        let mut states = vec![-1.0; a.birth_index];
+        states.extend(a.behaviour[a.birth_index..].iter().map(|b| *b as usize as f32));
+        states.resize(num_steps, -1.0);
+        values.push(states);
+    }
+    if let Err(e) = write_to_file(path, values) {
+        eprintln!("{}", e);
+    }
+}
+
+pub fn predators(model: &Model) -> impl Iterator<Item = &Agent> {
+    model.agents.iter().filter(|a| matches!(a.agent_type, AgentType::Predator(..)))
+}
+
+// Number of kills made by each predator, in agent order
+pub fn kills_per_predator(model: &Model) -> Vec<usize> {
+    predators(model).map(|a| a.kills.len()).collect()
+}
+
+// Fraction of all kills made by the most successful predator, 1.0 when one predator
+// does all the killing and 1/num_pred when kills are shared evenly
+pub fn kill_share(model: &Model) -> f32 {
+    let kills = kills_per_predator(model);
+    let total: usize = kills.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    *kills.iter().max().unwrap() as f32 / total as f32
+}
+
+// Total time an agent spent inside any refuge
+pub fn time_in_refuge(model: &Model, agent: &Agent) -> f32 {
+    let steps = agent.positions[agent.birth_index..]
+        .iter()
+        .filter(|p| model.in_refuge(p))
+        .count();
+    steps as f32 * model.times.dt
+}
+
+fn refuge_edge_distance(model: &Model, pos: &Vec2) -> f32 {
+    model
+        .refuges
+        .iter()
+        .map(|r| r.edge_distance(pos, model.bound_length()))
+        .fold(f32::INFINITY, f32::min)
+}
+
+// Distance from each kill to the nearest refuge edge. Captures inside refuges are not
+// allowed so these are never negative.
+pub fn kill_edge_distances(model: &Model) -> Vec<f32> {
+    let mut distances = Vec::new();
+    for a in prey(model) {
+        if let State::Dead(_, pos) = a.dead {
+            distances.push(refuge_edge_distance(model, &pos));
+        }
+    }
+    distances
+}
+
+// One row per prey: id, time in refuge, distance of its death from the nearest refuge edge
+// (-1 if it survived)
+pub fn output_refuge(path: String, model: &Model) {
+    let mut ids = Vec::new();
+    let mut times = Vec::new();
+    let mut edge_distances = Vec::new();
+    for a in prey(model) {
+        ids.push(a.id as f32);
+        times.push(time_in_refuge(model, a));
+        edge_distances.push(match a.dead {
+            State::Alive => -1.0,
+            State::Dead(_, pos) => refuge_edge_distance(model, &pos),
+        });
+    }
+    if let Err(e) = write_to_file(path, vec![ids, times, edge_distances]) {
+        eprintln!("{}", e);
+    }
+}
+
+pub fn total_intake(agent: &Agent) -> f32 {
+    agent.intake.iter().sum()
+}
+
+// Mean resource eaten per prey over the run, dead prey included
+pub fn mean_intake(model: &Model) -> f32 {
+    let total: f32 = prey(model).map(total_intake).sum();
+    total / model.num_prey as f32
+}
+
+// Cumulative intake of every prey at each time step. Columns are time then one column
+// per prey, padded with -1 before birth and holding the final value after death.
+pub fn output_intake(path: String, model: &Model) {
+    let num_steps = model.times.times.len();
+    let mut values = vec![model.times.times.clone()];
+    for a in prey(model) {
+        let mut cumulative = vec![-1.0; a.birth_index];
+        let mut sum = 0.0;
+        for eaten in a.intake[a.birth_index..].iter() {
+            sum += eaten;
+            cumulative.push(sum);
+        }
+        cumulative.resize(num_steps, sum);
+        values.push(cumulative);
+    }
+    if let Err(e) = write_to_file(path, values) {
+        eprintln!("{}", e);
+    }
+}
+
+const KILL_HEADER: [&str; 14] = [
+    "time", "time_index", "prey_id", "predator_id", "prey_x", "prey_y", "prey_vx", "prey_vy",
+    "predator_x", "predator_y", "predator_vx", "predator_vy", "local_density",
+    "centroid_distance",
+];
+
+fn kill_record(k: &KillEvent) -> Vec<String> {
+    vec![
+        k.time.to_string(),
+        k.time_index.to_string(),
+        k.prey_id.to_string(),
+        k.predator_id.to_string(),
+        k.prey_position.x.to_string(),
+        k.prey_position.y.to_string(),
+        k.prey_velocity.x.to_string(),
+        k.prey_velocity.y.to_string(),
+        k.predator_position.x.to_string(),
+        k.predator_position.y.to_string(),
+        k.predator_velocity.x.to_string(),
+        k.predator_velocity.y.to_string(),
+        k.local_density.to_string(),
+        k.centroid_distance.to_string(),
+    ]
+}
+
+pub fn output_kills_csv(path: String, model: &Model) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(KILL_HEADER)?;
+    for k in model.kills.iter() {
+        writer.write_record(kill_record(k))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn output_kills_json(path: String, model: &Model) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, serde_json::to_string_pretty(&model.kills)?)?;
+    Ok(())
+}
+
+// Speed of each prey at the moment it was caught
+pub fn kill_speeds(model: &Model) -> Vec<f32> {
+    model.kills.iter().map(|k| k.prey_velocity.length()).collect()
+}
+
+pub fn mean_kill_density(model: &Model) -> Option<f32> {
+    if model.kills.is_empty() {
+        return None;
+    }
+    let total: usize = model.kills.iter().map(|k| k.local_density).sum();
+    Some(total as f32 / model.kills.len() as f32)
+}
+
+// Proportion of kills made on prey further than distance from the group centroid
+pub fn peripheral_kill_fraction(model: &Model, distance: f32) -> f32 {
+    if model.kills.is_empty() {
+        return 0.0;
+    }
+    let peripheral = model.kills.iter().filter(|k| k.centroid_distance > distance).count();
+    peripheral as f32 / model.kills.len() as f32
+}
+
+// Polarisation of the prey alive at a time step, safe to call while a run is in progress
+pub fn prey_order(model: &Model, time_step: usize) -> f32 {
+    let mut order = Vec2::ZERO;
+    let mut count = 0;
+    for a in prey(model) {
+        let alive = match a.dead {
+            State::Dead(index, _) => index > time_step,
+            State::Alive => true,
+        };
+        if alive && a.birth_index <= time_step && time_step < a.velocities.len() {
+            order += a.velocities[time_step].normalize_or_zero();
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return 0.0;
+    }
+    order.length() / count as f32
+}
+
+pub fn prop_alive(model: &Model) -> f32 {
+    let alive = prey(model).filter(|a| matches!(a.dead, State::Alive)).count();
+    alive as f32 / model.num_prey as f32
+}
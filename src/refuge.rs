@@ -0,0 +1,73 @@
+use ggez::glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+// Positions and lengths are fractions of bound_length
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Shape {
+    Circle { centre: Vec2, radius: f32 },
+    Rect { xmin: f32, xmax: f32, ymin: f32, ymax: f32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PredatorAccess {
+    Open,
+    // Predators inside move at this fraction of their max_vel
+    Slowed(f32),
+    Closed,
+}
+
+// Region where prey cannot be captured
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Refuge {
+    pub shape: Shape,
+    pub predator_access: PredatorAccess,
+}
+
+impl Refuge {
+    // Distance to the refuge edge, negative inside
+    pub fn edge_distance(&self, pos: &Vec2, bound_length: f32) -> f32 {
+        match &self.shape {
+            Shape::Circle { centre, radius } => {
+                (*pos - *centre * bound_length).length() - radius * bound_length
+            }
+            Shape::Rect { xmin, xmax, ymin, ymax } => {
+                let dx = (xmin * bound_length - pos.x).max(pos.x - xmax * bound_length);
+                let dy = (ymin * bound_length - pos.y).max(pos.y - ymax * bound_length);
+                if dx > 0.0 || dy > 0.0 {
+                    Vec2::new(dx.max(0.0), dy.max(0.0)).length()
+                } else {
+                    dx.max(dy)
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, pos: &Vec2, bound_length: f32) -> bool {
+        self.edge_distance(pos, bound_length) < 0.0
+    }
+
+    // Unit vector towards the closest point of the refuge, zero inside
+    pub fn direction(&self, pos: &Vec2, bound_length: f32) -> Vec2 {
+        if self.contains(pos, bound_length) {
+            return Vec2::ZERO;
+        }
+        let closest = match &self.shape {
+            Shape::Circle { centre, .. } => *centre * bound_length,
+            Shape::Rect { xmin, xmax, ymin, ymax } => Vec2::new(
+                pos.x.clamp(xmin * bound_length, xmax * bound_length),
+                pos.y.clamp(ymin * bound_length, ymax * bound_length),
+            ),
+        };
+        (closest - *pos).normalize_or_zero()
+    }
+}
+
+// Refuge with the closest edge to pos
+pub fn nearest<'a>(refuges: &'a [Refuge], pos: &Vec2, bound_length: f32) -> Option<&'a Refuge> {
+    refuges.iter().min_by(|a, b| {
+        a.edge_distance(pos, bound_length)
+            .partial_cmp(&b.edge_distance(pos, bound_length))
+            .unwrap()
+    })
+}
@@ -0,0 +1,89 @@
+use crate::model::BC;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Species {
+    Prey,
+    Predator,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    // Add N agents of a species using the species' current parameters
+    Spawn(Species, usize),
+    // Remove N randomly chosen living agents of a species
+    Remove(Species, usize),
+    // Change a named parameter (e.g. "noise", "prey_alignment") for every agent of a species
+    SetParam(Species, String, f32),
+    SetBC(BC),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimedEvent {
+    pub time: f32,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub events: Vec<TimedEvent>,
+    #[serde(default)]
+    next: usize,
+}
+
+impl Default for Schedule {
+    fn default() -> Schedule {
+        Schedule::new()
+    }
+}
+
+impl Schedule {
+    pub fn new() -> Schedule {
+        Schedule {
+            events: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn at(mut self, time: f32, event: Event) -> Schedule {
+        self.events.push(TimedEvent { time, event });
+        self.sort();
+        self
+    }
+
+    // due relies on events being in time order, ties keep the order they were given in
+    pub fn sort(&mut self) {
+        self.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    // Linearly change a parameter between values (from, to) over times (start, end) in
+    // `steps` events
+    pub fn ramp(
+        mut self,
+        species: Species,
+        name: &str,
+        (from, to): (f32, f32),
+        (start, end): (f32, f32),
+        steps: usize,
+    ) -> Schedule {
+        for i in 0..=steps {
+            let frac = i as f32 / steps.max(1) as f32;
+            self = self.at(
+                start + frac * (end - start),
+                Event::SetParam(species, name.to_owned(), from + frac * (to - from)),
+            );
+        }
+        self
+    }
+
+    // Events that are due at time t and have not been applied yet
+    pub fn due(&mut self, t: f32) -> Vec<Event> {
+        let mut events = Vec::new();
+        while self.next < self.events.len() && self.events[self.next].time <= t {
+            events.push(self.events[self.next].event.clone());
+            self.next += 1;
+        }
+        events
+    }
+}
@@ -0,0 +1,29 @@
+use crate::model::Model;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+// Reasons for Model::run to finish before times.endtime
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StopCondition {
+    AllPreyDead,
+    // Stop once the fraction of prey alive is at or below this value
+    PreyFraction(f32),
+    // Prey order parameter stays within tolerance of its value for duration seconds
+    OrderStable { tolerance: f32, duration: f32 },
+    WallClock(Duration),
+    // Cannot be written to a checkpoint or scenario file
+    #[serde(skip)]
+    Custom(fn(&Model) -> bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StopReason {
+    EndTime,
+    AllPreyDead,
+    PreyFraction,
+    OrderStable,
+    WallClock,
+    Custom,
+    // An observer returned Control::Stop
+    Observer,
+}
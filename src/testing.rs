@@ -1,48 +1,41 @@
-use crate::boid::{Agent, AgentType, PreyParams, PredParams};
+use crate::boid::{PreyParams, PredParams};
 use crate::graphics::CREAM;
 use crate::model::{Model, Parameters, BC, Time};
 use crate::plot::*;
 use ggez::glam::Vec2;
 use std;
 use crate::parameter_search;
+use std::error::Error;
 
 // function to import JSONs
-pub fn import_result(name: &str) -> parameter_search::Result {
+pub fn import_result(name: &str) -> Result<parameter_search::Result, Box<dyn Error>> {
     let filename: String = format!("json/results_{}.json", name);
-    let output_string: String = std::fs::read_to_string(filename).unwrap();
-    let mut output_json: parameter_search::Result = serde_json::from_str(&output_string).unwrap();
-    output_json
+    let output_string: String = std::fs::read_to_string(&filename)
+        .map_err(|e| format!("{}: {}", filename, e))?;
+    let output_json: parameter_search::Result = serde_json::from_str(&output_string)
+        .map_err(|e| format!("{}: {}", filename, e))?;
+    Ok(output_json)
 }
 
 //function to build parameter set from results
 pub fn build_params(result: &parameter_search::Result, physical_params: Vec<f32>,
                         space_params: Vec<f32>, index: Vec<usize>) -> Parameters {
     let prey_params = PreyParams {
-        vision_radius: 1.0,
-        current_direction: 0.0, // not in use
         prey_alignment: result.prey_behaviour_params[index[1]][0] as f32,
         prey_attraction: result.prey_behaviour_params[index[1]][1] as f32,
         prey_repulsion: result.prey_behaviour_params[index[1]][2] as f32,
         predator_alignment: result.prey_behaviour_params[index[1]][3] as f32,
-        predator_centering: 0.0,
         predator_repulsion: result.prey_behaviour_params[index[1]][4] as f32,
-        max_acceleration: 2.0,
-        max_vel: 1.0,
-        boundary: 20.0, // not in use
+        ..PreyParams::new()
     };
     let pred_params = PredParams {
-        vision_radius: 2.0,
-        current_direction: 0.0, // not in use
-        prey_alignment: 0.0,
         prey_attraction: result.pred_behaviour_params[index[0]][0] as f32, //0.1813,
-        nearest_prey: 0.0, // not in use
         predator_alignment: result.pred_behaviour_params[index[0]][1] as f32, //0.5662,
         predator_attraction: result.pred_behaviour_params[index[0]][2] as f32, //0.1217,
         predator_repulsion: result.pred_behaviour_params[index[0]][3] as f32, //0.0315,
         max_acceleration: physical_params[0],
         max_vel: physical_params[1],
-        boundary: 20.0, //not in use
-        cooldown: 0.5,
+        ..PredParams::new()
     };
     Parameters {
         // Model
@@ -53,6 +46,7 @@ pub fn build_params(result: &parameter_search::Result, physical_params: Vec<f32>
         times: Time::new(1.0 / 60.0, 300.0),
         prey_params,
         pred_params,
+        ..Parameters::default()
     }
 }
 
@@ -0,0 +1,52 @@
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+
+// Distribution of a single parameter across the agents of a species
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Dist {
+    Fixed(f32),
+    Normal { mean: f32, std: f32 },
+    Uniform { min: f32, max: f32 },
+    // (weight, component) pairs, weights need not sum to one
+    Mixture(Vec<(f32, Dist)>),
+}
+
+impl Dist {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> f32 {
+        match self {
+            Dist::Fixed(value) => *value,
+            Dist::Normal { mean, std } => Normal::new(*mean, *std)
+                .expect("Invalid standard deviation")
+                .sample(rng),
+            Dist::Uniform { min, max } => {
+                if max > min {
+                    rng.gen_range(*min..*max)
+                } else {
+                    *min
+                }
+            }
+            Dist::Mixture(components) => {
+                let total: f32 = components.iter().map(|(w, _)| w).sum();
+                let mut u = rng.gen::<f32>() * total;
+                for (weight, dist) in components.iter() {
+                    if u < *weight {
+                        return dist.sample(rng);
+                    }
+                    u -= weight;
+                }
+                components.last().expect("Empty mixture").1.sample(rng)
+            }
+        }
+    }
+}
+
+// Named parameters (as accepted by PreyParams::set / PredParams::set) drawn per agent
+pub type Variation = Vec<(String, Dist)>;
+
+pub fn sample_traits<R: Rng>(variation: &Variation, rng: &mut R) -> Vec<(String, f32)> {
+    variation
+        .iter()
+        .map(|(name, dist)| (name.clone(), dist.sample(rng)))
+        .collect()
+}
@@ -0,0 +1,20 @@
+use boids::field::Pheromone;
+use ggez::glam::Vec2;
+
+#[test]
+fn periodic_pheromone_wraps_round_the_edges() {
+    let mut pheromone = Pheromone::new(4, 1.0, 0.0);
+    pheromone.field.add(&Vec2::new(0.5, 0.5), 4.0, 1.0);
+
+    // The deposit is only a neighbour of the last column across the periodic edge
+    let gradient = pheromone.field.gradient(&Vec2::new(3.5, 0.5), 4.0, true);
+    assert!(gradient.x > 0.0);
+    let gradient = pheromone.field.gradient(&Vec2::new(3.5, 0.5), 4.0, false);
+    assert_eq!(gradient.x, 0.0);
+
+    pheromone.update(4.0, 0.1, true);
+    let values = &pheromone.field.values;
+    assert!(values[3] > 0.0);
+    assert!(values[3 * 4] > 0.0);
+    assert!((pheromone.field.total() - 1.0).abs() < 1e-6);
+}
@@ -0,0 +1,138 @@
+use boids::boid::{AgentType, Kinematics};
+use boids::flow::{Flow, FlowField};
+use boids::model::{Model, Parameters, Time, IC};
+use boids::plot::output_traits;
+use boids::refuge::{PredatorAccess, Refuge, Shape};
+use boids::variation::Dist;
+use ggez::glam::Vec2;
+
+fn single(x: f32, y: f32) -> IC {
+    IC::School { centre: Vec2::new(x, y), radius: 0.0, heading: 0.0 }
+}
+
+#[test]
+fn predator_without_noise_or_prey_in_range_keeps_moving() {
+    let mut params = Parameters {
+        num_prey: 1,
+        num_pred: 1,
+        times: Time::new(0.05, 1.0),
+        prey_ic: single(0.8, 0.5),
+        pred_ic: single(0.2, 0.5),
+        ..Parameters::default()
+    };
+    params.pred_params.noise = 0.0;
+    let mut model = Model::from(&params);
+    model.run();
+    let predator = &model.agents[1];
+    let start = predator.positions[0];
+    let end = predator.positions[model.times.current_index];
+    assert!(end.is_finite());
+    assert!((end - start).length() > 0.5);
+}
+
+#[test]
+fn short_initial_condition_file_is_reported_by_validate() {
+    let path = std::env::temp_dir().join("boids_short_ic.csv");
+    std::fs::write(&path, "1.0,2.0,1.0,0.0\n3.0,4.0,0.0,1.0\n").unwrap();
+    let params = Parameters {
+        num_pred: 3,
+        pred_ic: IC::File(path.to_str().unwrap().to_string()),
+        ..Parameters::default()
+    };
+    let error = params.validate().unwrap_err();
+    assert!(error.contains("pred_ic.File"));
+    assert!(error.contains("has 2 rows but 3 agents were requested"));
+}
+
+#[test]
+fn flow_file_is_loaded_into_a_grid_and_checked_by_validate() {
+    let path = std::env::temp_dir().join("boids_flow.csv");
+    std::fs::write(&path, "1.0,0.0\n1.0,0.0\n1.0,0.0\n1.0,0.0\n").unwrap();
+    let field = FlowField::File(path.to_str().unwrap().to_string());
+    match field.load().unwrap() {
+        FlowField::Grid { n, values } => assert_eq!((n, values.len()), (2, 4)),
+        other => panic!("expected a grid, got {:?}", other),
+    }
+
+    std::fs::write(&path, "1.0,0.0\n1.0,0.0\n1.0,0.0\n").unwrap();
+    let params = Parameters {
+        flow: Flow { field, ..Flow::still() },
+        ..Parameters::default()
+    };
+    let error = params.validate().unwrap_err();
+    assert!(error.contains("flow.field.File"));
+    assert!(error.contains("square number of rows, got 3"));
+}
+
+#[test]
+fn traits_are_written_for_both_species_by_name() {
+    let params = Parameters {
+        num_prey: 2,
+        num_pred: 1,
+        times: Time::new(0.05, 0.1),
+        prey_variation: vec![("max_vel".to_owned(), Dist::Fixed(1.5))],
+        pred_variation: vec![
+            ("max_turn_rate".to_owned(), Dist::Fixed(2.0)),
+            ("max_vel".to_owned(), Dist::Fixed(3.0)),
+        ],
+        ..Parameters::default()
+    };
+    let model = Model::from(&params);
+    let path = std::env::temp_dir().join("boids_traits.csv");
+    output_traits(path.to_str().unwrap().to_string(), &model).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "id,species,death_time,max_vel,max_turn_rate");
+    assert!(lines[1].starts_with("0,prey,-1,1.5,"));
+    assert!(lines[1].ends_with(','));
+    assert!(lines[3].ends_with("predator,-1,3,2"));
+    match &model.agents[2].agent_type {
+        AgentType::Predator(_, pred) => match pred.kinematics {
+            Kinematics::TurnRate { max_turn_rate, .. } => assert_eq!(max_turn_rate, 2.0),
+            Kinematics::Free => panic!("max_turn_rate should switch to TurnRate"),
+        },
+        _ => panic!("expected a predator"),
+    }
+}
+
+fn closed_refuge() -> Refuge {
+    Refuge {
+        shape: Shape::Circle { centre: Vec2::new(0.5, 0.5), radius: 0.1 },
+        predator_access: PredatorAccess::Closed,
+    }
+}
+
+#[test]
+fn predator_inside_a_closed_refuge_can_leave() {
+    let mut params = Parameters {
+        num_prey: 1,
+        num_pred: 1,
+        times: Time::new(0.05, 2.0),
+        prey_ic: single(0.1, 0.9),
+        pred_ic: single(0.5, 0.5),
+        refuges: vec![closed_refuge()],
+        ..Parameters::default()
+    };
+    params.pred_params.noise = 0.0;
+    let mut model = Model::from(&params);
+    model.run();
+    let predator = &model.agents[1];
+    let end = predator.positions[model.times.current_index];
+    assert!(!model.refuges[0].contains(&end, params.bound_length));
+}
+
+#[test]
+fn predators_starting_inside_a_closed_refuge_are_rejected() {
+    let params = Parameters {
+        pred_ic: IC::School { centre: Vec2::new(0.5, 0.5), radius: 0.05, heading: 0.0 },
+        refuges: vec![closed_refuge()],
+        ..Parameters::default()
+    };
+    assert!(params.validate().unwrap_err().contains("pred_ic lies wholly inside refuges[0]"));
+
+    let params = Parameters {
+        pred_ic: IC::School { centre: Vec2::new(0.5, 0.5), radius: 0.2, heading: 0.0 },
+        ..params
+    };
+    assert!(params.validate().is_ok());
+}
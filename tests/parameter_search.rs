@@ -0,0 +1,8 @@
+use boids::parameter_search::Result;
+
+#[test]
+fn results_saved_before_the_rename_still_load() {
+    let text = std::fs::read_to_string("json/results_110.json").unwrap();
+    let result: Result = serde_json::from_str(&text).unwrap();
+    assert_eq!(result.final_scores.len(), 8);
+}